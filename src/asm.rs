@@ -1,12 +1,202 @@
 use indoc::indoc;
 use std::io::Write;
 
-use crate::chunk_list::ChunkList;
-use crate::ir::IR::{self, *};
+use crate::config::{Bounds, CellWidth, Config, Eof};
+use crate::ir::IR::*;
+use crate::ir::{DUMP_CELLS, Node, Span};
 
-pub fn to_asm(link_libc: bool, ir: ChunkList<IR>, writer: &mut impl Write) -> anyhow::Result<()> {
+/// the operand size for a cell
+fn size(width: CellWidth) -> &'static str {
+    match width {
+        CellWidth::W8 => "byte",
+        CellWidth::W16 => "word",
+        CellWidth::W32 => "dword",
+        CellWidth::W64 => "qword",
+    }
+}
+
+/// `rax` at the cell width
+fn acc(width: CellWidth) -> &'static str {
+    match width {
+        CellWidth::W8 => "al",
+        CellWidth::W16 => "ax",
+        CellWidth::W32 => "eax",
+        CellWidth::W64 => "rax",
+    }
+}
+
+/// the address of the cell at the tape index in `reg` -- `r14` points at cell 0
+fn tape(width: CellWidth, reg: &str) -> String {
+    match width.bytes() {
+        1 => format!("[r14 + {reg}]"),
+        scale => format!("[r14 + {reg}*{scale}]"),
+    }
+}
+
+/// an immediate operand for `value` wrapped to the cell width -- 64-bit values that don't fit
+/// in a sign-extended 32-bit immediate are loaded into `rdx` first
+fn imm(writer: &mut impl Write, width: CellWidth, value: i64) -> std::io::Result<String> {
+    let value = width.wrap_signed(value);
+    if i32::try_from(value).is_ok() {
+        return Ok(value.to_string());
+    }
+
+    writeln!(writer, "mov rdx, {value}")?;
+
+    Ok("rdx".to_string())
+}
+
+/// generates the code that keeps the pointer (`r8`) and the cells around it on the tape
+struct Tape {
+    config: Config,
+    /// the number of bounds checks so far, for labels
+    checks: usize,
+}
+
+impl Tape {
+    /// wrap the tape index in `reg`, which is less than two tape lengths
+    fn wrap(&self, writer: &mut impl Write, reg: &str) -> std::io::Result<()> {
+        let tape_size = self.config.tape_size;
+        if tape_size.is_power_of_two() {
+            writeln!(writer, "and {reg}, {:#x}", tape_size - 1)
+        } else {
+            writeln!(writer, "lea r11, [{reg} - {tape_size}]")?;
+            writeln!(writer, "cmp {reg}, {tape_size}")?;
+            writeln!(writer, "cmovae {reg}, r11")
+        }
+    }
+
+    /// compare the tape index in `reg` with the tape size
+    fn compare(&self, writer: &mut impl Write, reg: &str) -> std::io::Result<()> {
+        let tape_size = self.config.tape_size;
+        if i32::try_from(tape_size).is_ok() {
+            writeln!(writer, "cmp {reg}, {tape_size}")
+        } else {
+            writeln!(writer, "mov r11, {tape_size}")?;
+            writeln!(writer, "cmp {reg}, r11")
+        }
+    }
+
+    /// handle the tape index in `reg` being off the tape, by aborting or growing the tape
+    fn check(&mut self, writer: &mut impl Write, reg: &str, span: Span) -> std::io::Result<()> {
+        let label = self.checks;
+        self.checks += 1;
+
+        match self.config.bounds {
+            Bounds::Wrap => unreachable!("wrapped indices are always on the tape"),
+            Bounds::Abort => {
+                self.compare(writer, reg)?;
+                writeln!(writer, "jb b{label:x}")?;
+                writeln!(writer, "mov rdx, {reg}")?;
+                writeln!(writer, "mov rdi, {}", span.start.line)?;
+                writeln!(writer, "mov rsi, {}", span.start.column)?;
+                writeln!(writer, "jmp oob")?;
+            }
+            Bounds::Grow => {
+                writeln!(writer, "mov rdi, {reg}")?;
+                writeln!(writer, "sub rdi, [tape_low]")?;
+                writeln!(writer, "cmp rdi, [tape_len]")?;
+                writeln!(writer, "jb b{label:x}")?;
+                writeln!(writer, "mov rdi, {reg}")?;
+                writeln!(writer, "call g")?;
+            }
+        }
+
+        writeln!(writer, "b{label:x}:")
+    }
+
+    /// get the tape index `offset` cells away from the pointer into `reg`, unless it's the
+    /// pointer itself
+    fn cell<'a>(
+        &mut self,
+        writer: &mut impl Write,
+        reg: &'a str,
+        offset: isize,
+        span: Span,
+    ) -> std::io::Result<&'a str> {
+        if offset == 0 {
+            self.check_pointer(writer, span)?;
+            return Ok("r8");
+        }
+
+        if self.config.bounds == Bounds::Wrap {
+            let offset = offset.rem_euclid(self.config.tape_size as isize);
+            writeln!(writer, "lea {reg}, [r8 + {offset}]")?;
+            self.wrap(writer, reg)?;
+        } else {
+            writeln!(writer, "lea {reg}, [r8{offset:+}]")?;
+            self.check(writer, reg, span)?;
+        }
+
+        Ok(reg)
+    }
+
+    /// skip the rest of a multiply if the cell at the tape index in `reg` is zero -- they stand in
+    /// for loops, so the targets can only leave the tape when the loop would have run. returns the
+    /// label to put after the multiply.
+    fn skip_if_zero(
+        &mut self,
+        writer: &mut impl Write,
+        reg: &str,
+    ) -> std::io::Result<Option<usize>> {
+        if self.config.bounds == Bounds::Wrap {
+            return Ok(None);
+        }
+
+        let label = self.checks;
+        self.checks += 1;
+        let width = self.config.cell_width;
+        writeln!(writer, "cmp {} {}, 0", size(width), tape(width, reg))?;
+        writeln!(writer, "jz b{label:x}")?;
+
+        Ok(Some(label))
+    }
+
+    /// stop the program at `span` unless the pointer is on the tape -- with `Bounds::Abort` it
+    /// can leave it (see `shift`)
+    fn check_pointer(&mut self, writer: &mut impl Write, span: Span) -> std::io::Result<()> {
+        match self.config.bounds {
+            Bounds::Abort => self.check(writer, "r8", span),
+            Bounds::Wrap | Bounds::Grow => Ok(()),
+        }
+    }
+
+    /// move the pointer by `amount`. with `Bounds::Abort` it can end up off the tape -- that's
+    /// only stopped once a cell off it is touched.
+    fn shift(&mut self, writer: &mut impl Write, amount: isize, span: Span) -> std::io::Result<()> {
+        match self.config.bounds {
+            Bounds::Wrap => {
+                let amount = amount.rem_euclid(self.config.tape_size as isize);
+                writeln!(writer, "add r8, {amount}")?;
+                self.wrap(writer, "r8")
+            }
+            Bounds::Abort => writeln!(writer, "add r8, {amount}"),
+            Bounds::Grow => {
+                writeln!(writer, "add r8, {amount}")?;
+                self.check(writer, "r8", span)
+            }
+        }
+    }
+}
+
+/// `input` is baked into the executable for `,` to read instead of stdin (see `--extensions`)
+pub fn to_asm(
+    link_libc: bool,
+    ir: Vec<Node>,
+    config: Config,
+    input: Option<Vec<u8>>,
+    writer: &mut impl Write,
+) -> anyhow::Result<()> {
+    let width = config.cell_width;
+    let size = size(width);
+    let acc = acc(width);
+    let mut tape_code = Tape { config, checks: 0 };
     let mut label_stack = Vec::new();
     let mut current_label = 0;
+    // constant output and tape images as (data directive, values), written out at the end
+    let mut data = Vec::new();
+    // whether there's a `#` to dump the tape for
+    let mut dumps = false;
 
     writeln!(
         writer,
@@ -16,33 +206,31 @@ pub fn to_asm(link_libc: bool, ir: ChunkList<IR>, writer: &mut impl Write) -> an
         public _start
 
         section '.bss' writable
-        tape rb 65536
+        tape rb {tape_size} * {cell_bytes}
+        input_byte rb 1
 
         section '.text' executable
         _start:
         xor r8, r8
-    "}
+        lea r14, [tape]
+    "},
+        tape_size = config.tape_size,
+        cell_bytes = width.bytes()
     )?;
 
-    for inst in ir {
+    for Node { inst, span } in ir {
         match inst {
-            Shift { amount: a @ ..0 } => {
-                writeln!(writer, "sub r8, {}", a.abs())?;
-                writeln!(writer, "and r8, 0xFFFF")?;
-            }
-            Shift { amount: a @ 0.. } => {
-                writeln!(writer, "add r8, {}", a.abs())?;
-                writeln!(writer, "and r8, 0xFFFF")?;
-            }
-            Arithmetic { amount: a @ ..0 } => {
-                writeln!(writer, "sub byte [tape + r8], {}", a.abs())?;
-            }
-            Arithmetic { amount: a @ 0.. } => {
-                writeln!(writer, "add byte [tape + r8], {}", a.abs())?;
+            Shift { amount } => tape_code.shift(writer, amount, span)?,
+            Arithmetic { amount, offset } => {
+                let c = tape_code.cell(writer, "r9", offset, span)?;
+                let amount = imm(writer, width, amount)?;
+                writeln!(writer, "add {size} {}, {amount}", tape(width, c))?;
             }
             LoopStart { .. } => {
+                // `]` checks the pointer before coming back around
+                tape_code.check_pointer(writer, span)?;
                 writeln!(writer, "o{current_label:x}:")?;
-                writeln!(writer, "cmp byte [tape + r8], 0")?;
+                writeln!(writer, "cmp {size} {}, 0", tape(width, "r8"))?;
                 writeln!(writer, "jz c{current_label:x}")?;
                 label_stack.push(current_label);
                 current_label += 1;
@@ -51,37 +239,138 @@ pub fn to_asm(link_libc: bool, ir: ChunkList<IR>, writer: &mut impl Write) -> an
                 let Some(opening_label) = label_stack.pop() else {
                     unreachable!()
                 };
+                tape_code.check_pointer(writer, span)?;
                 writeln!(writer, "jmp o{opening_label:x}")?;
                 writeln!(writer, "c{opening_label:x}:")?;
             }
-            Input => {
+            Input { offset } => {
+                let c = tape_code.cell(writer, "r9", offset, span)?;
+                writeln!(writer, "lea rsi, {}", tape(width, c))?;
                 writeln!(writer, "call i")?;
             }
-            Output => {
+            Output { offset } => {
+                let c = tape_code.cell(writer, "r9", offset, span)?;
+                writeln!(writer, "lea rsi, {}", tape(width, c))?;
                 writeln!(writer, "call o")?;
             }
 
             // idioms
-            Zero => {
-                writeln!(writer, "call z")?;
+            Zero { offset } => {
+                let c = tape_code.cell(writer, "r9", offset, span)?;
+                writeln!(writer, "mov {size} {}, 0", tape(width, c))?;
+            }
+            Set { value, offset } => {
+                let c = tape_code.cell(writer, "r9", offset, span)?;
+                let value = imm(writer, width, value as i64)?;
+                writeln!(writer, "mov {size} {}, {value}", tape(width, c))?;
             }
             Multiply {
                 amount,
+                offset,
                 output_offset,
             } => {
-                writeln!(writer, "mov r13b, {amount}")?;
-                writeln!(writer, "mov r12, {}", output_offset.abs())?;
-                writeln!(writer, "call m{}", if output_offset > 0 { "" } else { "s" })?;
+                let src = tape_code.cell(writer, "r9", offset, span)?;
+                let skip = tape_code.skip_if_zero(writer, src)?;
+                let dst = tape_code.cell(writer, "r10", offset + output_offset, span)?;
+                writeln!(writer, "mov {acc}, {}", width.wrap_signed(amount))?;
+                writeln!(writer, "mul {size} {}", tape(width, src))?;
+                writeln!(writer, "add {size} {}, {acc}", tape(width, dst))?;
+                writeln!(writer, "mov {size} {}, 0", tape(width, src))?;
+                if let Some(label) = skip {
+                    writeln!(writer, "b{label:x}:")?;
+                }
             }
-            Move { output_offset } => {
-                writeln!(writer, "mov r12, {}", output_offset.abs())?;
-                writeln!(writer, "call M{}", if output_offset > 0 { "" } else { "s" })?;
+            Move {
+                offset,
+                output_offset,
+            } => {
+                let src = tape_code.cell(writer, "r9", offset, span)?;
+                let skip = tape_code.skip_if_zero(writer, src)?;
+                let dst = tape_code.cell(writer, "r10", offset + output_offset, span)?;
+                writeln!(writer, "mov {acc}, {size} {}", tape(width, src))?;
+                writeln!(writer, "add {size} {}, {acc}", tape(width, dst))?;
+                writeln!(writer, "mov {size} {}, 0", tape(width, src))?;
+                if let Some(label) = skip {
+                    writeln!(writer, "b{label:x}:")?;
+                }
+            }
+            MultiplyAdd { offset, targets } => {
+                let src = tape_code.cell(writer, "r9", offset, span)?;
+                let skip = tape_code.skip_if_zero(writer, src)?;
+                for (output_offset, coefficient) in targets {
+                    let dst = tape_code.cell(writer, "r10", offset + output_offset, span)?;
+                    writeln!(writer, "mov {acc}, {}", width.wrap_signed(coefficient))?;
+                    writeln!(writer, "mul {size} {}", tape(width, src))?;
+                    writeln!(writer, "add {size} {}, {acc}", tape(width, dst))?;
+                }
+                writeln!(writer, "mov {size} {}, 0", tape(width, src))?;
+                if let Some(label) = skip {
+                    writeln!(writer, "b{label:x}:")?;
+                }
+            }
+            Print { bytes } => {
+                writeln!(writer, "mov rax, 1")?;
+                writeln!(writer, "mov rdi, 1")?;
+                writeln!(writer, "lea rsi, byte [d{:x}]", data.len())?;
+                writeln!(writer, "mov rdx, {}", bytes.len())?;
+                writeln!(writer, "syscall")?;
+                data.push(("db", bytes.into_iter().map(u64::from).collect()));
+            }
+            Preload { mut cells } => {
+                // the parser turns away longer tape images, but only the tape's worth fits
+                cells.truncate(config.tape_size);
+                writeln!(writer, "lea rsi, byte [d{:x}]", data.len())?;
+                writeln!(writer, "mov rdi, r14")?;
+                writeln!(writer, "mov rcx, {}", cells.len() * width.bytes())?;
+                writeln!(writer, "rep movsb")?;
+                let directive = match width {
+                    CellWidth::W8 => "db",
+                    CellWidth::W16 => "dw",
+                    CellWidth::W32 => "dd",
+                    CellWidth::W64 => "dq",
+                };
+                data.push((directive, cells));
             }
-            AnchorRight => {
-                writeln!(writer, "call r")?;
+            Scan { stride, target } => {
+                tape_code.check_pointer(writer, span)?;
+                writeln!(writer, "mov r13, {target}")?;
+                match config.bounds {
+                    Bounds::Wrap => match stride {
+                        1 if link_libc && width == CellWidth::W8 => writeln!(writer, "call r")?,
+                        -1 if link_libc && width == CellWidth::W8 => writeln!(writer, "call l")?,
+                        _ => {
+                            let stride = stride.rem_euclid(config.tape_size as isize);
+                            writeln!(writer, "mov r12, {stride}")?;
+                            writeln!(writer, "call s")?;
+                        }
+                    },
+                    // `s` stops at the edge of the tape. every cell past it is zero, so the
+                    // scan either ends on the first one or never ends.
+                    Bounds::Grow if target != 0 => {
+                        writeln!(writer, "mov r12, {stride}")?;
+                        writeln!(writer, "call s")?;
+                        writeln!(writer, "mov rdi, r8")?;
+                        writeln!(writer, "sub rdi, [tape_low]")?;
+                        writeln!(writer, "cmp rdi, [tape_len]")?;
+                        writeln!(
+                            writer,
+                            "jae halting_problem_solved_100_percent_working_1936"
+                        )?;
+                    }
+                    Bounds::Abort | Bounds::Grow => {
+                        writeln!(writer, "mov r12, {stride}")?;
+                        writeln!(writer, "call s")?;
+                        tape_code.check(writer, "r8", span)?;
+                    }
+                }
             }
-            AnchorLeft => {
-                writeln!(writer, "call l")?;
+            DebugDump => {
+                let header = format!("# {span} -- pointer on cell ");
+                writeln!(writer, "lea rsi, byte [d{:x}]", data.len())?;
+                writeln!(writer, "mov rdx, {}", header.len())?;
+                writeln!(writer, "call dump")?;
+                data.push(("db", header.bytes().map(u64::from).collect()));
+                dumps = true;
             }
         }
     }
@@ -96,198 +385,483 @@ pub fn to_asm(link_libc: bool, ir: ChunkList<IR>, writer: &mut impl Write) -> an
 
         ; little assembly 'macros' to slim down code
 
-        ; `,` in brainf*ck -- gets one character of user input
+        ; `,` in brainf*ck -- gets one character of user input into the cell at `rsi`
         i:
-        xor rax, rax
-        xor rdi, rdi
-        lea rsi, byte [tape + r8]
-        mov rdx, 1
-        syscall
+        {read}
+        mov {size} [rsi], {acc}
+        ret
+        i_eof:
+        {eof}
         ret
 
-        ; `.` in brainf*ck -- outputs the current byte in ascii
+        ; `.` in brainf*ck -- outputs the cell at `rsi` in ascii
         o:
         mov rax, 1
         mov rdi, 1
-        lea rsi, byte [tape + r8]
         mov rdx, 1
         syscall
         ret
 
         ; idioms
 
-        ; zero current byte
-        z:
-        mov byte [tape + r8], 0
-        ret
-
-        ; these last four can go burn in hell
-
-        ; multiply (positive output offset)
-        m:
-        add r12, r8
-        and r12, 0xFFFF
-        mov al, r13b
-        mul byte [tape + r8]
-        add byte [tape + r12], al
-        mov byte [tape + r8], 0
-        ret
+        ; scan -- move the pointer by `r12` until the current cell is `r13`
+        s:
+        cmp {size} {current}, {target}
+        je s_done
+        add r8, r12
+    "},
+        size = size,
+        acc = acc,
+        read = match input {
+            // the input baked in with `--extensions`
+            Some(_) => indoc! {"
+                mov rax, [input_pos]
+                cmp rax, input_len
+                jae i_eof
+                inc qword [input_pos]
+                lea rdx, [input_data]
+                movzx eax, byte [rdx + rax]"
+            },
+            None => indoc! {"
+                push rsi
+                xor rax, rax
+                xor rdi, rdi
+                lea rsi, [input_byte]
+                mov rdx, 1
+                syscall
+                pop rsi
+                test rax, rax
+                jle i_eof
+                movzx eax, byte [input_byte]"
+            },
+        },
+        eof = match config.eof {
+            Eof::Zero => format!("mov {size} [rsi], 0"),
+            Eof::MinusOne => format!("mov {size} [rsi], -1"),
+            Eof::Unchanged => String::new(),
+            Eof::Error => "jmp eof".to_string(),
+        },
+        current = tape(width, "r8"),
+        target = match width {
+            CellWidth::W8 => "r13b",
+            CellWidth::W16 => "r13w",
+            CellWidth::W32 => "r13d",
+            CellWidth::W64 => "r13",
+        }
+    )?;
 
-        ; multiply (negative output offset)
-        ms:
-        mov r14, r8
-        sub r14, r12
-        and r14, 0xFFFF
-        mov al, r13b
-        mul byte [tape + r8]
-        add byte [tape + r14], al
-        mov byte [tape + r8], 0
-        ret
+    // the scan stops at the edge of the tape unless it wraps
+    match config.bounds {
+        Bounds::Wrap => tape_code.wrap(writer, "r8")?,
+        Bounds::Abort => {
+            tape_code.compare(writer, "r8")?;
+            writeln!(writer, "jae s_done")?;
+        }
+        Bounds::Grow => {
+            writeln!(writer, "mov rdi, r8")?;
+            writeln!(writer, "sub rdi, [tape_low]")?;
+            writeln!(writer, "cmp rdi, [tape_len]")?;
+            writeln!(writer, "jae s_done")?;
+        }
+    }
 
-        ; move (positive output offset)
-        M:
-        add r12, r8
-        and r12, 0xFFFF
-        mov r13b, byte [tape + r8]
-        add byte [tape + r12], r13b
-        mov byte [tape + r8], 0
+    writeln!(
+        writer,
+        indoc! {"
+        jmp s
+        s_done:
         ret
 
-        ; move (negative output offset)
-        Ms:
-        mov r14, r8
-        sub r14, r12
-        and r14, 0xFFFF
-        mov r13b, byte [tape + r8]
-        add byte [tape + r14], r13b
-        mov byte [tape + r8], 0
-        ret
+        ; solve the halting problem
+        halting_problem_solved_100_percent_working_1936:
+        mov rax, 1
+        mov rdi, 1
+        lea rsi, byte [halting_message]
+        mov rdx, halting_message_len
+        syscall
+        mov rax, 60
+        mov rdi, 1
+        syscall
     "}
     )?;
 
-    if link_libc {
+    if config.eof == Eof::Error {
         writeln!(
             writer,
             indoc! {"
-            extrn memchr
-            extrn memrchr
+            ; the input ran out
+            eof:
+            mov rax, 1
+            mov rdi, 1
+            lea rsi, byte [eof_message]
+            mov rdx, eof_message_len
+            syscall
+            mov rax, 60
+            mov rdi, 1
+            syscall
+        "}
+        )?;
+    }
 
-            ; find right anchor (memchr-enabled)
-            r:
-            call anchor_start
-            lea rdi, byte [tape + r8]
-            mov rsi, 255
-            mov rdx, 0xFFFF
-            sub rdx, r8
-            call memchr
-            cmp rax, 0
-            jz r_wrap
-            jmp anchor_done
+    if config.bounds == Bounds::Abort {
+        writeln!(
+            writer,
+            indoc! {"
+            ; report the pointer leaving the tape and exit -- `rdi` and `rsi` are the line and
+            ; column of the instruction that did it, `rdx` is the cell it went to
+            oob:
+            mov r15, 1
+            mov r12, rdi
+            mov r13, rsi
+            mov rbx, rdx
+            lea rsi, [oob_message]
+            mov rdx, oob_message_len
+            call ps
+            mov rax, r12
+            call pn
+            lea rsi, [oob_colon]
+            mov rdx, 1
+            call ps
+            mov rax, r13
+            call pn
+            lea rsi, [oob_cell]
+            mov rdx, oob_cell_len
+            call ps
+            mov rax, rbx
+            call pn
+            lea rsi, [oob_end]
+            mov rdx, oob_end_len
+            call ps
+            mov rax, 60
+            mov rdi, 1
+            syscall
+        "}
+        )?;
+    }
+
+    if dumps {
+        let load = match width {
+            CellWidth::W8 | CellWidth::W16 => format!("movzx eax, {size} {}", tape(width, "r9")),
+            CellWidth::W32 => format!("mov eax, dword {}", tape(width, "r9")),
+            CellWidth::W64 => format!("mov rax, qword {}", tape(width, "r9")),
+        };
+        writeln!(
+            writer,
+            indoc! {"
+            ; `#` -- print `rdx` bytes from `rsi`, then the pointer and the first cells, to stderr
+            dump:
+            push r8
+            push r9
+            push r15
+            mov r15, 2
+            call ps
+            mov rax, r8
+            call pn
+            lea rsi, [dump_colon]
+            mov rdx, 1
+            call ps
+            xor r9, r9
+            dump_cell:
+            lea rsi, [dump_space]
+            mov rdx, 1
+            call ps
+            {load}
+            call pu
+            inc r9
+            cmp r9, {cells}
+            jb dump_cell
+            lea rsi, [dump_newline]
+            mov rdx, 1
+            call ps
+            pop r15
+            pop r9
+            pop r8
             ret
-            r_wrap:
-            lea rdi, byte [tape]
-            mov rsi, 255
-            mov rdx, r8
-            call memchr
-            cmp rax, 0
-            jz halting_problem_solved_100_percent_working_1936
-            jmp anchor_done
+        "},
+            load = load,
+            cells = DUMP_CELLS.min(config.tape_size),
+        )?;
+    }
 
-            ; find left anchor (memrchr-enabled)
-            l:
-            call anchor_start
-            lea rdi, byte [tape]
-            mov rsi, 255
-            mov rdx, r8
-            call memrchr
-            cmp rax, 0
-            jz l_wrap
-            jmp anchor_done
-            l_wrap:
-            lea rdi, byte [tape + r8]
-            mov rsi, 255
-            mov rdx, 0xFFFF
-            sub rdx, r8
-            call memrchr
-            cmp rax, 0
-            jz halting_problem_solved_100_percent_working_1936
-            jmp anchor_done
+    if config.bounds == Bounds::Abort || dumps {
+        writeln!(
+            writer,
+            indoc! {"
+            ; print `rax` as an unsigned decimal number
+            pu:
+            sub rsp, 32
+            lea rsi, [rsp + 32]
+            xor rcx, rcx
+            jmp pn_digit
 
-            ; common code
-            anchor_start:
-            cmp byte [tape + r8], 0
-            jz anchor_short_circuit
-            sub byte [tape + r8], 1
+            ; print `rax` as a signed decimal number
+            pn:
+            sub rsp, 32
+            lea rsi, [rsp + 32]
+            mov rcx, rax
+            test rax, rax
+            jns pn_digit
+            neg rax
+            pn_digit:
+            xor rdx, rdx
+            mov r11, 10
+            div r11
+            add dl, 48
+            dec rsi
+            mov byte [rsi], dl
+            test rax, rax
+            jnz pn_digit
+            test rcx, rcx
+            jns pn_write
+            dec rsi
+            mov byte [rsi], 45
+            pn_write:
+            lea rdx, [rsp + 32]
+            sub rdx, rsi
+            call ps
+            add rsp, 32
             ret
 
-            anchor_short_circuit:
-            add rsp, 8
+            ; print `rdx` bytes from `rsi` to the file in `r15`
+            ps:
+            mov rax, 1
+            mov rdi, r15
+            syscall
             ret
+        "}
+        )?;
+    }
 
-            anchor_done:
-            mov r8, rax
-            lea rax, byte [tape]
-            sub r8, rax
-            mov byte [tape + r8], 0
+    if config.bounds == Bounds::Grow {
+        writeln!(
+            writer,
+            indoc! {"
+            ; grow the tape (at least doubling it) until it has the cell at index `rdi`. cells
+            ; keep their indices, `r14` is moved to the new cell 0.
+            g:
+            push r8
+            push r9
+            push r10
+            push r12
+            push r13
+            mov r12, [tape_low]
+            mov r13, [tape_len]
+            lea r9, [r12 + r13]
+            mov r10, r12
+            cmp rdi, r12
+            jge g_right
+            sub r10, r13
+            cmp rdi, r10
+            cmovl r10, rdi
+            jmp g_map
+            g_right:
+            add r9, r13
+            lea rax, [rdi + 1]
+            cmp rax, r9
+            cmovg r9, rax
+            g_map:
+            ; `r10` is the new lowest index, `r9` the new length
+            sub r9, r10
+            push r9
+            push r10
+            ; mmap(0, length, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0)
+            mov rsi, r9
+            imul rsi, rsi, {cell_bytes}
+            mov rax, 9
+            xor rdi, rdi
+            mov rdx, 3
+            mov r10, 0x22
+            mov r8, -1
+            xor r9, r9
+            syscall
+            pop r10
+            pop r9
+            cmp rax, -4096
+            ja g_failed
+            ; copy the old cells to their place in the new tape
+            mov rdi, r12
+            sub rdi, r10
+            imul rdi, rdi, {cell_bytes}
+            add rdi, rax
+            mov rsi, [tape_start]
+            mov rcx, r13
+            imul rcx, rcx, {cell_bytes}
+            rep movsb
+            mov rdi, [tape_start]
+            mov [tape_start], rax
+            mov [tape_low], r10
+            mov [tape_len], r9
+            ; the first tape isn't mapped, so it can't be unmapped
+            lea rsi, [tape]
+            cmp rdi, rsi
+            je g_done
+            mov rsi, r13
+            imul rsi, rsi, {cell_bytes}
+            mov rax, 11
+            syscall
+            g_done:
+            mov rax, r10
+            imul rax, rax, {cell_bytes}
+            mov r14, [tape_start]
+            sub r14, rax
+            pop r13
+            pop r12
+            pop r10
+            pop r9
+            pop r8
             ret
 
-            ; solve the halting problem
-            halting_problem_solved_100_percent_working_1936:
+            g_failed:
             mov rax, 1
             mov rdi, 1
-            lea rsi, byte [halting_message]
-            mov rdx, halting_message_len
+            lea rsi, [grow_message]
+            mov rdx, grow_message_len
             syscall
             mov rax, 60
             mov rdi, 1
             syscall
-
-            section '.data'
-            halting_message db '[boyfriend] ! infinite loop detected, exiting', 0xA
-            halting_message_len = $-halting_message
-        "}
+        "},
+            cell_bytes = width.bytes()
         )?;
-    } else {
+    }
+
+    if link_libc {
         writeln!(
             writer,
             indoc! {"
-            ; find right anchor (no libc)
+            extrn memchr
+            extrn memrchr
+
+            ; `rbx` holds the pointer while calling into libc, pushing it also aligns the stack
+
+            ; scan right for `r13b` (memchr-enabled)
             r:
-            call anchor_start
-            r_glide:
-            add r8, 1
-            and r8, 0xFFFF
-            cmp byte [tape + r8], 255
-            jne r_glide
-            jmp anchor_end
-
-            ; find left anchor (no libc)
+            push rbx
+            mov rbx, r8
+            lea rdi, byte [r14 + rbx]
+            movzx rsi, r13b
+            mov rdx, {tape_size}
+            sub rdx, rbx
+            call memchr
+            test rax, rax
+            jnz scan_done
+            mov rdi, r14
+            movzx rsi, r13b
+            mov rdx, rbx
+            call memchr
+            test rax, rax
+            jz halting_problem_solved_100_percent_working_1936
+            jmp scan_done
+
+            ; scan left for `r13b` (memrchr-enabled)
             l:
-            call anchor_start
-            l_glide:
-            sub r8, 1
-            and r8, 0xFFFF
-            cmp byte [tape + r8], 255
-            jne l_glide
-            jmp anchor_end
+            push rbx
+            mov rbx, r8
+            mov rdi, r14
+            movzx rsi, r13b
+            lea rdx, [rbx + 1]
+            call memrchr
+            test rax, rax
+            jnz scan_done
+            lea rdi, byte [r14 + rbx]
+            movzx rsi, r13b
+            mov rdx, {tape_size}
+            sub rdx, rbx
+            call memrchr
+            test rax, rax
+            jz halting_problem_solved_100_percent_working_1936
+            jmp scan_done
 
             ; common code
-            anchor_start:
-            cmp byte [tape + r8], 0
-            jz anchor_short_circuit
-            sub byte [tape + r8], 1
+            scan_done:
+            sub rax, r14
+            mov r8, rax
+            pop rbx
             ret
+        "},
+            tape_size = config.tape_size
+        )?;
+    }
 
-            anchor_short_circuit:
-            add rsp, 8
-            ret
+    writeln!(
+        writer,
+        indoc! {"
+        section '.data' writable
+        halting_message db '[boyfriend] ! infinite loop detected, exiting', 0xA
+        halting_message_len = $-halting_message
+    "}
+    )?;
 
-            anchor_end:
-            mov byte [tape + r8], 0
-            ret
+    if let Some(input) = input {
+        writeln!(writer, "input_pos dq 0")?;
+        writeln!(writer, "input_len = {}", input.len())?;
+        writeln!(writer, "input_data:")?;
+        for line in input.chunks(32) {
+            let line = line.iter().map(u8::to_string).collect::<Vec<_>>();
+            writeln!(writer, "db {}", line.join(", "))?;
+        }
+    }
+
+    if dumps {
+        writeln!(
+            writer,
+            indoc! {"
+            dump_colon db ':'
+            dump_space db ' '
+            dump_newline db 0xA
         "}
         )?;
     }
 
+    if config.eof == Eof::Error {
+        writeln!(
+            writer,
+            indoc! {"
+            eof_message db '[boyfriend] ! the input ran out, exiting', 0xA
+            eof_message_len = $-eof_message
+        "}
+        )?;
+    }
+
+    if config.bounds == Bounds::Abort {
+        writeln!(
+            writer,
+            indoc! {"
+            oob_message db '[boyfriend] ! pointer out of bounds at '
+            oob_message_len = $-oob_message
+            oob_colon db ':'
+            oob_cell db ' (cell '
+            oob_cell_len = $-oob_cell
+            oob_end db '), exiting', 0xA
+            oob_end_len = $-oob_end
+        "}
+        )?;
+    }
+
+    if config.bounds == Bounds::Grow {
+        writeln!(
+            writer,
+            indoc! {"
+            grow_message db '[boyfriend] ! out of memory growing the tape, exiting', 0xA
+            grow_message_len = $-grow_message
+            tape_start dq tape
+            tape_low dq 0
+            tape_len dq {tape_size}
+        "},
+            tape_size = config.tape_size
+        )?;
+    }
+
+    if !data.is_empty() {
+        writeln!(writer, "section '.rodata'")?;
+        for (label, (directive, values)) in data.iter().enumerate() {
+            writeln!(writer, "d{label:x}:")?;
+            for line in values.chunks(32) {
+                let line = line.iter().map(u64::to_string).collect::<Vec<_>>();
+                writeln!(writer, "{directive} {}", line.join(", "))?;
+            }
+        }
+    }
+
     Ok(())
 }
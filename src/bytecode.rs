@@ -0,0 +1,450 @@
+use std::io::Write;
+
+use crate::config::{Bounds, CellWidth, Config, Eof, Overflow};
+use crate::error::{Error, Fault};
+use crate::fuel::{Exhausted, Fuel};
+use crate::interpret::{Cell, Reader, Writer, print_dump, scan};
+use crate::ir::IR::*;
+use crate::ir::{DUMP_CELLS, Node, Span};
+
+/// runs an op, returning the index of the next one (`HALT` to stop)
+type Handler<C> = fn(&mut Vm<C>, &Op<C>, usize) -> usize;
+
+/// returned by a handler to stop the program
+const HALT: usize = usize::MAX;
+
+/// one instruction of bytecode. every op is the same size, and carries the function that runs it
+/// so dispatching is a single indirect call.
+struct Op<C: Cell> {
+    run: Handler<C>,
+    /// the cell the op works on, as an offset from the pointer wrapped into the tape
+    offset: u32,
+    /// a second cell (wrapped like `offset`), where a jump goes, or an index into a data table
+    other: u32,
+    /// the amount, value or coefficient, already wrapped to a cell
+    value: C,
+}
+
+/// the state of a running bytecode program
+struct Vm<'a, C: Cell> {
+    tape: Vec<C>,
+    ptr: usize,
+    eof: Eof,
+    input: Reader<'a>,
+    output: Writer<'a>,
+    /// `Print` output and `Preload` tape images
+    prints: Vec<Vec<u8>>,
+    preloads: Vec<Vec<C>>,
+    /// where each op came from
+    spans: Vec<Span>,
+    /// what stopped the program early, and the op that did it
+    fault: Option<(Fault, usize)>,
+}
+
+impl<C: Cell> Vm<'_, C> {
+    /// the tape index `offset` cells to the right of the pointer -- both are on the tape, so one
+    /// subtraction wraps it
+    fn at(&self, offset: u32) -> usize {
+        let idx = self.ptr + offset as usize;
+        if idx >= self.tape.len() {
+            idx - self.tape.len()
+        } else {
+            idx
+        }
+    }
+
+    /// stop the program because of `fault` in the op at `ip`
+    fn fail(&mut self, fault: impl Into<Fault>, ip: usize) -> usize {
+        self.fault = Some((fault.into(), ip));
+
+        HALT
+    }
+}
+
+// the handlers
+
+fn add<C: Cell>(vm: &mut Vm<C>, op: &Op<C>, ip: usize) -> usize {
+    let idx = vm.at(op.offset);
+    vm.tape[idx] = vm.tape[idx].wrapping_add(op.value);
+    ip + 1
+}
+
+fn set<C: Cell>(vm: &mut Vm<C>, op: &Op<C>, ip: usize) -> usize {
+    let idx = vm.at(op.offset);
+    vm.tape[idx] = op.value;
+    ip + 1
+}
+
+fn shift<C: Cell>(vm: &mut Vm<C>, op: &Op<C>, ip: usize) -> usize {
+    vm.ptr = vm.at(op.offset);
+    ip + 1
+}
+
+/// `[` -- `other` is the op after the matching `]`
+fn jump_if_zero<C: Cell>(vm: &mut Vm<C>, op: &Op<C>, ip: usize) -> usize {
+    if vm.tape[vm.ptr] == C::default() {
+        op.other as usize
+    } else {
+        ip + 1
+    }
+}
+
+/// `]` -- `other` is the op after the matching `[`
+fn jump_if_nonzero<C: Cell>(vm: &mut Vm<C>, op: &Op<C>, ip: usize) -> usize {
+    if vm.tape[vm.ptr] != C::default() {
+        op.other as usize
+    } else {
+        ip + 1
+    }
+}
+
+/// a shift and the `]` right after it, which ends most loop bodies once shifts are sunk
+fn shift_jump_if_nonzero<C: Cell>(vm: &mut Vm<C>, op: &Op<C>, ip: usize) -> usize {
+    vm.ptr = vm.at(op.offset);
+    jump_if_nonzero(vm, op, ip)
+}
+
+/// add the cell at `offset` times `value` to the cell at `other`
+fn multiply_add<C: Cell>(vm: &mut Vm<C>, op: &Op<C>, ip: usize) -> usize {
+    let src = vm.tape[vm.at(op.offset)];
+    let dst = vm.at(op.other);
+    vm.tape[dst] = vm.tape[dst].wrapping_add(src.wrapping_mul(op.value));
+    ip + 1
+}
+
+/// `multiply_add`, then zero the cell at `offset` -- the last target of a multiply
+fn multiply_move<C: Cell>(vm: &mut Vm<C>, op: &Op<C>, ip: usize) -> usize {
+    let src = vm.at(op.offset);
+    let dst = vm.at(op.other);
+    vm.tape[dst] = vm.tape[dst].wrapping_add(vm.tape[src].wrapping_mul(op.value));
+    vm.tape[src] = C::default();
+    ip + 1
+}
+
+/// `multiply_move` with a coefficient of one
+fn move_add<C: Cell>(vm: &mut Vm<C>, op: &Op<C>, ip: usize) -> usize {
+    let src = vm.at(op.offset);
+    let dst = vm.at(op.other);
+    vm.tape[dst] = vm.tape[dst].wrapping_add(vm.tape[src]);
+    vm.tape[src] = C::default();
+    ip + 1
+}
+
+fn input<C: Cell>(vm: &mut Vm<C>, op: &Op<C>, ip: usize) -> usize {
+    let byte = match vm.output.flush().and_then(|()| vm.input.read()) {
+        Ok(byte) => byte,
+        Err(err) => return vm.fail(err, ip),
+    };
+
+    let idx = vm.at(op.offset);
+    match (byte, vm.eof) {
+        (Some(byte), _) => vm.tape[idx] = C::from_u64(byte as u64),
+        (None, Eof::Zero) => vm.tape[idx] = C::default(),
+        (None, Eof::MinusOne) => vm.tape[idx] = C::from_u64(u64::MAX),
+        (None, Eof::Unchanged) => {}
+        (None, Eof::Error) => return vm.fail(Fault::InputRanOut, ip),
+    }
+    ip + 1
+}
+
+fn output<C: Cell>(vm: &mut Vm<C>, op: &Op<C>, ip: usize) -> usize {
+    let byte = vm.tape[vm.at(op.offset)].to_u64() as u8;
+    if let Err(err) = vm.output.write(&[byte]) {
+        return vm.fail(err, ip);
+    }
+    ip + 1
+}
+
+/// print the bytes at index `other` in the print table
+fn print<C: Cell>(vm: &mut Vm<C>, op: &Op<C>, ip: usize) -> usize {
+    let Vm { prints, output, .. } = vm;
+    if let Err(err) = output.write(&prints[op.other as usize]) {
+        return vm.fail(err, ip);
+    }
+    ip + 1
+}
+
+/// copy the tape image at index `other` in the preload table to the start of the tape
+fn preload<C: Cell>(vm: &mut Vm<C>, op: &Op<C>, ip: usize) -> usize {
+    let cells = &vm.preloads[op.other as usize];
+    let len = cells.len().min(vm.tape.len());
+    vm.tape[..len].copy_from_slice(&cells[..len]);
+    ip + 1
+}
+
+/// move the pointer by the stride in `offset` until the cell under it is `value`
+fn scan_for<C: Cell>(vm: &mut Vm<C>, op: &Op<C>, ip: usize) -> usize {
+    // the stride is wrapped into the tape, but the fast paths want to know which way it goes
+    let stride = match op.offset as usize {
+        1 => 1,
+        stride if stride == vm.tape.len() - 1 => -1,
+        stride => stride as isize,
+    };
+    match scan(&vm.tape, vm.ptr, stride, op.value) {
+        Some(found) => {
+            vm.ptr = found;
+            ip + 1
+        }
+        None => vm.fail(Fault::InfiniteLoop, ip),
+    }
+}
+
+/// print the pointer and the first cells to stderr
+fn dump<C: Cell>(vm: &mut Vm<C>, _: &Op<C>, ip: usize) -> usize {
+    if let Err(err) = vm.output.flush() {
+        return vm.fail(err, ip);
+    }
+    let cells = vm.tape.iter().take(DUMP_CELLS);
+    print_dump(
+        vm.spans[ip],
+        vm.ptr as isize,
+        cells.map(|cell| cell.to_u64() as i128),
+    );
+    ip + 1
+}
+
+fn halt<C: Cell>(_: &mut Vm<C>, _: &Op<C>, _: usize) -> usize {
+    HALT
+}
+
+/// whether the bytecode interpreter can run programs with `config` -- it only does wrapping
+/// tapes and cells, and everything else is left to the IR interpreter
+pub fn supports(config: &Config) -> bool {
+    config.bounds == Bounds::Wrap && config.overflow == Overflow::Wrap
+}
+
+/// run `ir`, reading `input` for `,` and writing to `output`
+pub fn interpret<'a>(
+    ir: &[Node],
+    config: Config,
+    input: Reader<'a>,
+    output: &'a mut dyn Write,
+) -> Result<(), Error> {
+    match config.cell_width {
+        CellWidth::W8 => run::<u8>(ir, &config, input, output),
+        CellWidth::W16 => run::<u16>(ir, &config, input, output),
+        CellWidth::W32 => run::<u32>(ir, &config, input, output),
+        CellWidth::W64 => run::<u64>(ir, &config, input, output),
+    }
+}
+
+/// compiles IR into bytecode, keeping track of the data tables and where each op came from
+struct Assembler<C: Cell> {
+    code: Vec<Op<C>>,
+    spans: Vec<Span>,
+    /// the `[` of the innermost loop around each op, for `--max-steps` and `--timeout`
+    within: Vec<Option<Span>>,
+    /// how many IR instructions each op stands for, so steps are counted the same way as in the
+    /// IR interpreter
+    steps: Vec<u8>,
+    /// the indices of the `[`s that haven't been closed yet
+    open: Vec<usize>,
+    tape_size: usize,
+    prints: Vec<Vec<u8>>,
+    preloads: Vec<Vec<C>>,
+}
+
+impl<C: Cell> Assembler<C> {
+    /// `offset` wrapped into the tape
+    fn wrap(&self, offset: isize) -> u32 {
+        offset.rem_euclid(self.tape_size as isize) as u32
+    }
+
+    fn push(&mut self, run: Handler<C>, offset: u32, other: u32, value: C, span: Span) {
+        self.code.push(Op {
+            run,
+            offset,
+            other,
+            value,
+        });
+        self.spans.push(span);
+        self.within
+            .push(self.open.last().map(|&start| self.spans[start]));
+        self.steps.push(1);
+    }
+
+    fn assemble(&mut self, ir: &[Node]) {
+        for (idx, &Node { ref inst, span }) in ir.iter().enumerate() {
+            match *inst {
+                Shift { amount } => self.push(shift, self.wrap(amount), 0, C::default(), span),
+                Arithmetic { amount, offset } => {
+                    self.push(add, self.wrap(offset), 0, C::from_u64(amount as u64), span)
+                }
+                LoopStart { .. } => {
+                    self.push(jump_if_zero, 0, 0, C::default(), span);
+                    self.open.push(self.code.len() - 1);
+                }
+                LoopEnd { .. } => {
+                    let start = *self
+                        .open
+                        .last()
+                        .expect("brackets are verified before compiling");
+                    // fold a shift right before the `]` into it
+                    if let Shift { .. } = ir[idx - 1].inst {
+                        let last = self.code.len() - 1;
+                        self.code[last].run = shift_jump_if_nonzero;
+                        self.code[last].other = start as u32 + 1;
+                        self.spans[last] = self.spans[last].merge(span);
+                        self.steps[last] += 1;
+                    } else {
+                        self.push(jump_if_nonzero, 0, start as u32 + 1, C::default(), span);
+                    }
+                    self.code[start].other = self.code.len() as u32;
+                    self.open.pop();
+                }
+                Input { offset } => self.push(input, self.wrap(offset), 0, C::default(), span),
+                Output { offset } => self.push(output, self.wrap(offset), 0, C::default(), span),
+                Zero { offset } => self.push(set, self.wrap(offset), 0, C::default(), span),
+                Set { value, offset } => {
+                    self.push(set, self.wrap(offset), 0, C::from_u64(value), span)
+                }
+                Multiply {
+                    amount,
+                    offset,
+                    output_offset,
+                } => self.push(
+                    multiply_move,
+                    self.wrap(offset),
+                    self.wrap(offset + output_offset),
+                    C::from_u64(amount as u64),
+                    span,
+                ),
+                Move {
+                    offset,
+                    output_offset,
+                } => self.push(
+                    move_add,
+                    self.wrap(offset),
+                    self.wrap(offset + output_offset),
+                    C::default(),
+                    span,
+                ),
+                MultiplyAdd {
+                    offset,
+                    ref targets,
+                } => {
+                    if targets.is_empty() {
+                        self.push(set, self.wrap(offset), 0, C::default(), span);
+                    }
+                    for (idx, &(output_offset, coefficient)) in targets.iter().enumerate() {
+                        let run = match idx == targets.len() - 1 {
+                            true => multiply_move,
+                            false => multiply_add,
+                        };
+                        self.push(
+                            run,
+                            self.wrap(offset),
+                            self.wrap(offset + output_offset),
+                            C::from_u64(coefficient as u64),
+                            span,
+                        );
+                        // the whole multiply is one step, taken on its first op
+                        if idx > 0 {
+                            *self.steps.last_mut().unwrap() = 0;
+                        }
+                    }
+                }
+                Print { ref bytes } => {
+                    self.prints.push(bytes.clone());
+                    let idx = self.prints.len() as u32 - 1;
+                    self.push(print, 0, idx, C::default(), span);
+                }
+                Preload { ref cells } => {
+                    self.preloads
+                        .push(cells.iter().map(|&cell| C::from_u64(cell)).collect());
+                    let idx = self.preloads.len() as u32 - 1;
+                    self.push(preload, 0, idx, C::default(), span);
+                }
+                Scan { stride, target } => {
+                    self.push(scan_for, self.wrap(stride), 0, C::from_u64(target), span)
+                }
+                DebugDump => self.push(dump, 0, 0, C::default(), span),
+            }
+        }
+
+        let span = self.spans.last().copied().unwrap_or(Span::at(1, 1));
+        self.push(halt, 0, 0, C::default(), span);
+        *self.steps.last_mut().unwrap() = 0;
+    }
+}
+
+fn run<'a, C: Cell>(
+    ir: &[Node],
+    config: &Config,
+    input: Reader<'a>,
+    output: &'a mut dyn Write,
+) -> Result<(), Error> {
+    let mut assembler = Assembler {
+        code: Vec::with_capacity(ir.len() + 1),
+        spans: Vec::with_capacity(ir.len() + 1),
+        within: Vec::with_capacity(ir.len() + 1),
+        steps: Vec::with_capacity(ir.len() + 1),
+        open: Vec::new(),
+        tape_size: config.tape_size,
+        prints: Vec::new(),
+        preloads: Vec::new(),
+    };
+    assembler.assemble(ir);
+    let Assembler {
+        code,
+        spans,
+        within,
+        steps,
+        prints,
+        preloads,
+        ..
+    } = assembler;
+
+    let mut vm = Vm {
+        tape: vec![C::default(); config.tape_size],
+        ptr: 0,
+        eof: config.eof,
+        input,
+        output: Writer::new(output),
+        prints,
+        preloads,
+        spans,
+        fault: None,
+    };
+
+    let mut ip = 0;
+    // the limit that stopped the program, and the op it was about to run
+    let mut exhausted = None;
+    match Fuel::new(config) {
+        None => {
+            while ip != HALT {
+                let op = &code[ip];
+                ip = (op.run)(&mut vm, op, ip);
+            }
+        }
+        // counting steps is kept out of the usual loop
+        Some(mut fuel) => {
+            while ip != HALT {
+                if let Err(limit) = (0..steps[ip]).try_for_each(|_| fuel.burn()) {
+                    exhausted = Some((limit, ip));
+                    break;
+                }
+                let op = &code[ip];
+                ip = (op.run)(&mut vm, op, ip);
+            }
+        }
+    }
+    vm.output.flush()?;
+
+    if let Some((fault, ip)) = vm.fault {
+        return Err(Error::Fault {
+            fault,
+            span: vm.spans[ip],
+        });
+    }
+    if let Some((limit, ip)) = exhausted {
+        return Err(Exhausted {
+            limit,
+            span: within[ip].unwrap_or(vm.spans[ip]),
+            in_loop: within[ip].is_some(),
+        }
+        .into());
+    }
+
+    Ok(())
+}
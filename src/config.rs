@@ -0,0 +1,142 @@
+use std::time::Duration;
+
+use clap::ValueEnum;
+
+/// how a program behaves when it runs -- every backend (and partial evaluation) follows it
+#[derive(Clone, Copy)]
+pub struct Config {
+    pub cell_width: CellWidth,
+    /// the number of cells on the tape (its starting size with `Bounds::Grow`)
+    pub tape_size: usize,
+    pub bounds: Bounds,
+    pub eof: Eof,
+    pub overflow: Overflow,
+    /// stop the program after this many steps (see `fuel`)
+    pub max_steps: Option<u64>,
+    /// stop the program once it's run for this long
+    pub timeout: Option<Duration>,
+}
+
+/// what the command line does without any options
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            cell_width: CellWidth::W8,
+            tape_size: 65536,
+            bounds: Bounds::Wrap,
+            eof: Eof::Unchanged,
+            overflow: Overflow::Wrap,
+            max_steps: None,
+            timeout: None,
+        }
+    }
+}
+
+impl Config {
+    /// the number held by a cell with the bits `value`
+    pub fn cell_value(&self, value: u64) -> i128 {
+        match self.overflow {
+            Overflow::Signed => self.cell_width.wrap_signed(value as i64) as i128,
+            Overflow::Wrap | Overflow::Unsigned => value as i128,
+        }
+    }
+
+    /// the smallest and largest numbers a cell can hold
+    pub fn cell_range(&self) -> (i128, i128) {
+        let max = self.cell_width.max() as i128;
+        match self.overflow {
+            Overflow::Signed => (-(max / 2) - 1, max / 2),
+            Overflow::Wrap | Overflow::Unsigned => (0, max),
+        }
+    }
+
+    /// the bits for storing `value` in a cell -- `None` if it overflows and cells don't wrap
+    pub fn fit(&self, value: i128) -> Option<u64> {
+        let (min, max) = self.cell_range();
+        match self.overflow {
+            Overflow::Wrap => Some(value as u64 & self.cell_width.max()),
+            Overflow::Unsigned | Overflow::Signed => (min..=max)
+                .contains(&value)
+                .then_some(value as u64 & self.cell_width.max()),
+        }
+    }
+}
+
+/// what happens when a cell goes past the largest or smallest number it can hold
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Overflow {
+    /// Come back around from the other end
+    Wrap,
+    /// Cells hold 0 and up, stop the program if one leaves that range
+    Unsigned,
+    /// Cells hold signed numbers, stop the program if one leaves their range
+    Signed,
+}
+
+/// what happens when the pointer leaves the tape
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Bounds {
+    /// Come back in on the other side of the tape
+    Wrap,
+    /// Stop the program once it touches a cell off the tape, reporting where it happened
+    Abort,
+    /// Make the tape bigger, in either direction
+    Grow,
+}
+
+/// what `,` does once the input has run out
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Eof {
+    /// Set the cell to zero
+    Zero,
+    /// Set the cell to -1 (its largest value)
+    MinusOne,
+    /// Leave the cell as it was
+    Unchanged,
+    /// Stop the program with an error
+    Error,
+}
+
+/// the size of a cell on the tape
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CellWidth {
+    #[value(name = "8")]
+    W8,
+    #[value(name = "16")]
+    W16,
+    #[value(name = "32")]
+    W32,
+    #[value(name = "64")]
+    W64,
+}
+
+impl CellWidth {
+    pub fn bits(self) -> u32 {
+        match self {
+            Self::W8 => 8,
+            Self::W16 => 16,
+            Self::W32 => 32,
+            Self::W64 => 64,
+        }
+    }
+
+    pub fn bytes(self) -> usize {
+        self.bits() as usize / 8
+    }
+
+    /// the largest value a cell can hold
+    pub fn max(self) -> u64 {
+        u64::MAX >> (64 - self.bits())
+    }
+
+    /// wrap `value` into a cell, eg. -1 becomes 255 with 8-bit cells
+    pub fn wrap(self, value: i64) -> u64 {
+        value as u64 & self.max()
+    }
+
+    /// the same value as `wrap`, but read as a signed number of the cell's width
+    pub fn wrap_signed(self, value: i64) -> i64 {
+        let shift = 64 - self.bits();
+        (value << shift) >> shift
+    }
+}
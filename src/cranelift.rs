@@ -1,17 +1,81 @@
 use std::any::Any;
+use std::io::Write;
 use std::mem::transmute;
+use std::ops::Range;
 
+use cranelift::codegen::ir::{FuncRef, Function, GlobalValue, GlobalValueData};
 use cranelift::jit::{JITBuilder, JITModule};
-use cranelift::module::{DataDescription, FuncId, Linkage, Module};
+use cranelift::module::{DataDescription, DataId, FuncId, Linkage, Module};
 pub use cranelift::object::object;
 use cranelift::object::{ObjectBuilder, ObjectModule};
 use cranelift::prelude::*;
 use object::write::Object;
 
-use crate::chunk_list::ChunkList;
-use crate::ir::IR::{self, *};
+use crate::config::{Bounds, CellWidth, Config, Eof, Overflow};
+use crate::error::{Error, Fault};
+use crate::fuel::{Exhausted, Fuel};
+use crate::interpret::{Reader, Writer};
+use crate::ir::IR::*;
+use crate::ir::{self, DUMP_CELLS, Node, Span};
 
-pub fn jit(ir: ChunkList<IR>) -> anyhow::Result<()> {
+// declare libc functions in a function with *macro magic*
+macro_rules! dlsym {
+    ($module:ident, $func:expr; $(fn $name:ident($($arg:ident),*) $(-> $($ret:ident),+)?;)+) => {
+        $(
+            let $name = {
+                #[allow(unused_imports)]
+                use types::*;
+
+                let mut sig = $module.make_signature();
+                $(sig.params.push(AbiParam::new($arg));)*
+                $($(sig.returns.push(AbiParam::new($ret));)+)?
+                let local_ref =
+                    $module.declare_function(stringify!($name), Linkage::Import, &sig)?;
+                $module.declare_func_in_func(local_ref, $func)
+            };
+        )+
+    };
+}
+
+/// compile `ir` and run it right away, reading `input` for `,` and writing to `output`. with
+/// `split_loops`, loops are compiled into functions of their own (see `--split-loops`).
+pub fn jit<'a>(
+    ir: Vec<Node>,
+    config: Config,
+    split_loops: bool,
+    input: Reader<'a>,
+    output: &'a mut dyn Write,
+) -> Result<(), Error> {
+    let (module, id) =
+        compile_jit(ir, config, split_loops).map_err(|err| Error::Codegen(format!("{err:#}")))?;
+
+    let mut session = Session {
+        config,
+        input,
+        output: Writer::new(output),
+        fuel: Fuel::new(&config),
+        error: None,
+    };
+    let entry_ptr = module.get_finalized_function(id);
+    let entry = unsafe { transmute::<*const u8, extern "C" fn(&mut Session)>(entry_ptr) };
+    entry(&mut session);
+    // nothing points into the module anymore
+    unsafe { module.free_memory() };
+
+    let flushed = session.output.flush();
+    if let Some(err) = session.error {
+        return Err(err);
+    }
+    flushed?;
+
+    Ok(())
+}
+
+fn compile_jit(
+    ir: Vec<Node>,
+    config: Config,
+    split_loops: bool,
+) -> anyhow::Result<(JITModule, FuncId)> {
     // set up cranelift module & context
     let mut flag_builder = settings::builder();
     flag_builder.set("use_colocated_libcalls", "false")?;
@@ -22,21 +86,38 @@ pub fn jit(ir: ChunkList<IR>) -> anyhow::Result<()> {
     let isa = isa_builder
         .finish(settings::Flags::new(flag_builder))
         .unwrap();
-    let builder = JITBuilder::with_isa(isa, cranelift::module::default_libcall_names());
+    let mut builder = JITBuilder::with_isa(isa, cranelift::module::default_libcall_names());
+    builder.symbol("session_input", session_input as *const u8);
+    builder.symbol("session_output", session_output as *const u8);
+    builder.symbol("session_print", session_print as *const u8);
+    builder.symbol("session_flush", session_flush as *const u8);
+    builder.symbol("session_fault", session_fault as *const u8);
+    builder.symbol("session_refuel", session_refuel as *const u8);
     let mut module = JITModule::new(builder);
 
-    let id = go(ir, &mut module)?;
+    let id = go(ir, &mut module, config, None, true, split_loops)?;
     module.finalize_definitions()?;
 
-    let entry_ptr = module.get_finalized_function(id);
-    let entry = unsafe { transmute::<*const u8, fn() -> ()>(entry_ptr) };
-
-    entry();
+    Ok((module, id))
+}
 
-    Ok(())
+/// compile `ir` into an object file with a `main` that uses libc for input and output. `input` is
+/// baked in and read by `,` instead of stdin (see `--extensions`).
+pub fn aot(
+    ir: Vec<Node>,
+    config: Config,
+    split_loops: bool,
+    input: Option<Vec<u8>>,
+) -> Result<Object<'static>, Error> {
+    compile_aot(ir, config, split_loops, input).map_err(|err| Error::Codegen(format!("{err:#}")))
 }
 
-pub fn aot(ir: ChunkList<IR>) -> anyhow::Result<Object<'static>> {
+fn compile_aot(
+    ir: Vec<Node>,
+    config: Config,
+    split_loops: bool,
+    input: Option<Vec<u8>>,
+) -> anyhow::Result<Object<'static>> {
     // set up cranelift module & context
     let mut flag_builder = settings::builder();
     flag_builder.set("is_pic", "true")?;
@@ -49,241 +130,2034 @@ pub fn aot(ir: ChunkList<IR>) -> anyhow::Result<Object<'static>> {
     let builder = ObjectBuilder::new(isa, "boyfriend", cranelift::module::default_libcall_names())?;
     let mut module = ObjectModule::new(builder);
 
-    go(ir, &mut module)?;
+    go(ir, &mut module, config, input, false, split_loops)?;
 
     let object = module.finish();
 
     Ok(object.object)
 }
 
-fn go<M: Any + Module>(ir: ChunkList<IR>, module: &mut M) -> anyhow::Result<FuncId> {
-    let mut ctx = module.make_context();
+/// how cells are found, and what happens when the pointer leaves the tape
+enum Guard {
+    Wrap,
+    /// the tape stays put, so the pointer is kept as the address of the current cell (see
+    /// `Tape::direct`)
+    Abort,
+    Grow {
+        grow: FuncRef,
+        /// where the address of cell 0, the lowest index on the tape, and the tape's length are
+        /// kept
+        state: Value,
+    },
+}
 
-    let size_t = module.target_config().pointer_type();
+/// a cell on the tape, found by `Tape::place`
+#[derive(Clone, Copy)]
+struct Place {
+    /// the cell's tape index, to blame it for errors
+    idx: Value,
+    addr: Value,
+    /// bytes past `addr`, folded into loads and stores
+    offset: i32,
+}
 
-    // initialize entry point
-    let mut builder_context = FunctionBuilderContext::new();
-    let mut builder = FunctionBuilder::new(&mut ctx.func, &mut builder_context);
-    let entry_block = builder.create_block();
-    builder.switch_to_block(entry_block);
-    builder.seal_block(entry_block);
-    let tape_ptr = Variable::from_u32(0);
-    builder.declare_var(tape_ptr, size_t);
-    let zero = builder.ins().iconst(size_t, 0);
-    builder.def_var(tape_ptr, zero);
-
-    // initialize tape
-    let tape_id = {
-        let mut data_description = DataDescription::new();
-        data_description.define_zeroinit(0x10000);
-        let tape_data_id = module.declare_data("tape", Linkage::Export, true, false)?;
-        module.define_data(tape_data_id, &data_description)?;
-        if let Some(jit_module) = (module as &mut dyn Any).downcast_mut::<JITModule>() {
-            jit_module.finalize_definitions()?;
-        }
-
-        module.declare_data_in_func(tape_data_id, builder.func)
-    };
+impl Place {
+    fn load(&self, builder: &mut FunctionBuilder, cell_t: Type) -> Value {
+        builder
+            .ins()
+            .load(cell_t, MemFlags::new(), self.addr, self.offset)
+    }
 
-    // initialize dlsym functions with *macro magic*
-    macro_rules! dlsym {
-        ($(fn $name:ident($($arg:ident),*) $(-> $ret:ident)?;)+) => {
-            $(dlsym!(fn $name($($arg),*) $(-> $ret)?);)+
-        };
+    fn store(&self, builder: &mut FunctionBuilder, value: Value) {
+        builder
+            .ins()
+            .store(MemFlags::new(), value, self.addr, self.offset);
+    }
+}
 
-        (fn $name:ident($($arg:ident),*) $(-> $ret:ident)?) => {
-            let $name = {
-                use types::*;
+/// generates the code that keeps the pointer and the cells around it on the tape
+struct Tape {
+    config: Config,
+    size_t: Type,
+    /// the pointer, as an index into the tape -- or the address of the current cell, if `direct`
+    ptr: Variable,
+    /// the address of cell 0, unless the tape grows
+    base: Variable,
+    guard: Guard,
+    host: Host,
+    /// the block being built, and the lowest and highest offsets from the pointer checked to be
+    /// on the tape in it (see `check_direct`)
+    checked: std::cell::Cell<Option<(Block, isize, isize)>>,
+}
 
-                let mut sig = module.make_signature();
-                $(sig.params.push(AbiParam::new($arg));)*
-                $(sig.returns.push(AbiParam::new($ret));)?
-                let local_ref = module.declare_function(stringify!($name), Linkage::Import, &sig)?;
-                module.declare_func_in_func(local_ref, builder.func)
-            };
-        };
+impl Tape {
+    /// whether the pointer is kept as an address, which saves working out the address of every
+    /// cell from its index. only a tape that neither wraps nor moves can do that.
+    fn direct(&self) -> bool {
+        matches!(self.guard, Guard::Abort)
+    }
+
+    /// the pointer, as an index into the tape
+    fn position(&self, builder: &mut FunctionBuilder) -> Value {
+        let ptr = builder.use_var(self.ptr);
+        if !self.direct() {
+            return ptr;
+        }
+
+        let base = builder.use_var(self.base);
+        let bytes = builder.ins().isub(ptr, base);
+        match self.config.cell_width.bytes() {
+            1 => bytes,
+            size => builder.ins().sshr_imm(bytes, size.trailing_zeros() as i64),
+        }
+    }
+
+    /// get the tape index `offset` cells away from the pointer, making sure it's on the tape
+    fn index(&self, builder: &mut FunctionBuilder, offset: isize, span: Span) -> Value {
+        let ptr = builder.use_var(self.ptr);
+        if offset == 0 {
+            return ptr;
+        }
+
+        let tape_size = self.config.tape_size as i64;
+        if let Guard::Wrap = self.guard {
+            let added = builder
+                .ins()
+                .iadd_imm(ptr, (offset as i64).rem_euclid(tape_size));
+            if self.config.tape_size.is_power_of_two() {
+                return builder.ins().band_imm(added, tape_size - 1);
+            }
+
+            let wrapped = builder.ins().iadd_imm(added, -tape_size);
+            let past_end =
+                builder
+                    .ins()
+                    .icmp_imm(IntCC::UnsignedGreaterThanOrEqual, added, tape_size);
+            return builder.ins().select(past_end, wrapped, added);
+        }
+
+        let idx = builder.ins().iadd_imm(ptr, offset as i64);
+        self.check(builder, idx, span);
+
+        idx
     }
 
-    dlsym! {
-        fn getchar() -> I8;
-        fn putchar(I8) -> I64;
-        fn memchr(size_t, I8, size_t) -> size_t;
-        fn memrchr(size_t, I8, size_t) -> size_t;
+    /// handle the tape index `idx` being off a growing tape by growing it, stopping the program
+    /// at `span` if there's no memory left for that
+    fn check(&self, builder: &mut FunctionBuilder, idx: Value, span: Span) {
+        let Guard::Grow { grow, state } = self.guard else {
+            unreachable!("only growing tapes check indices")
+        };
+
+        let off_tape_block = builder.create_block();
+        let on_tape_block = builder.create_block();
+        builder.set_cold_block(off_tape_block);
+        let off_tape = self.off_growing_tape(builder, state, idx);
+        builder
+            .ins()
+            .brif(off_tape, off_tape_block, &[], on_tape_block, &[]);
+
+        builder.switch_to_block(off_tape_block);
+        builder.seal_block(off_tape_block);
+        let call = builder.ins().call(grow, &[state, idx]);
+        let failed = builder.inst_results(call)[0];
+        self.host
+            .stop_if(builder, failed, Stop::OutOfMemory, span, idx);
+        builder.ins().jump(on_tape_block, &[]);
+
+        builder.switch_to_block(on_tape_block);
+        builder.seal_block(on_tape_block);
     }
 
-    let mut loops = Vec::new();
+    /// stop the program unless the cell `offset` cells away from a direct pointer is on the tape
+    /// (or `count` is zero, see `target`). every cell between two that were checked in the same
+    /// block is on the tape too, so those aren't checked again.
+    fn check_direct(
+        &self,
+        builder: &mut FunctionBuilder,
+        offset: isize,
+        count: Option<Value>,
+        span: Span,
+    ) {
+        let block = builder.current_block().unwrap();
+        let known = match self.checked.get() {
+            Some((checked_block, low, high)) if checked_block == block => Some((low, high)),
+            _ => None,
+        };
+        if known.is_some_and(|(low, high)| (low..=high).contains(&offset)) {
+            return;
+        }
 
-    for inst in ir {
-        match inst {
-            Shift { amount } => {
-                let tape_ptr_value = builder.use_var(tape_ptr);
-                let added = builder.ins().iadd_imm(tape_ptr_value, amount as i64);
-                let anded = builder.ins().band_imm(added, 0xFFFF);
-                builder.def_var(tape_ptr, anded);
+        let bytes = self.config.cell_width.bytes() as i64;
+        let tape_bytes = self.config.tape_size as i64 * bytes;
+        let ptr = builder.use_var(self.ptr);
+        let base = builder.use_var(self.base);
+        let addr = builder.ins().iadd_imm(ptr, offset as i64 * bytes);
+        let off_tape = match known {
+            // past a cell that's on the tape, only the edge in the direction of `offset` can be
+            // passed
+            Some((low, _)) if offset < low => {
+                builder.ins().icmp(IntCC::UnsignedLessThan, addr, base)
             }
-            Arithmetic { amount } => {
-                let tape_start = builder.ins().symbol_value(size_t, tape_id);
-
-                let tape_ptr_value = builder.use_var(tape_ptr);
-                let (addr, _overflow) = builder.ins().uadd_overflow(tape_start, tape_ptr_value);
-                let current_value = builder.ins().load(types::I8, MemFlags::new(), addr, 0);
-                let new_value = builder.ins().iadd_imm(current_value, amount as i64);
-                builder.ins().store(MemFlags::new(), new_value, addr, 0);
+            Some(_) => {
+                let end = builder.ins().iadd_imm(base, tape_bytes);
+                builder
+                    .ins()
+                    .icmp(IntCC::UnsignedGreaterThanOrEqual, addr, end)
             }
-            LoopStart { .. } => {
-                let start_block = builder.create_block();
-                let body_block = builder.create_block();
-                let after_block = builder.create_block();
-
-                builder.ins().jump(start_block, &[]);
-                builder.switch_to_block(start_block);
-                let tape_ptr_value = builder.use_var(tape_ptr);
-                let tape_start = builder.ins().symbol_value(size_t, tape_id);
-                let (addr, _overflow) = builder.ins().uadd_overflow(tape_start, tape_ptr_value);
-                let current_value = builder.ins().load(types::I8, MemFlags::new(), addr, 0);
+            None => {
+                let relative = builder.ins().isub(addr, base);
                 builder
                     .ins()
-                    .brif(current_value, body_block, &[], after_block, &[]);
+                    .icmp_imm(IntCC::UnsignedGreaterThanOrEqual, relative, tape_bytes)
+            }
+        };
+        let off_tape = match count {
+            Some(count) => {
+                let running = builder.ins().icmp_imm(IntCC::NotEqual, count, 0);
+                builder.ins().band(off_tape, running)
+            }
+            None => off_tape,
+        };
 
-                builder.switch_to_block(body_block);
-                builder.seal_block(body_block);
+        let off_tape_block = builder.create_block();
+        let on_tape_block = builder.create_block();
+        builder.set_cold_block(off_tape_block);
+        builder
+            .ins()
+            .brif(off_tape, off_tape_block, &[], on_tape_block, &[]);
+
+        builder.switch_to_block(off_tape_block);
+        builder.seal_block(off_tape_block);
+        let position = self.position(builder);
+        let idx = builder.ins().iadd_imm(position, offset as i64);
+        self.host.stop(builder, Stop::OutOfBounds, span, idx, None);
+
+        builder.switch_to_block(on_tape_block);
+        builder.seal_block(on_tape_block);
+        let known = match (count, known) {
+            (Some(_), known) => known,
+            (None, Some((low, high))) => Some((low.min(offset), high.max(offset))),
+            (None, None) => Some((offset, offset)),
+        };
+        self.checked
+            .set(known.map(|(low, high)| (on_tape_block, low, high)));
+    }
+
+    /// whether the tape index `idx` is off a growing tape
+    fn off_growing_tape(&self, builder: &mut FunctionBuilder, state: Value, idx: Value) -> Value {
+        let low = builder
+            .ins()
+            .load(self.size_t, MemFlags::trusted(), state, 8);
+        let len = builder
+            .ins()
+            .load(self.size_t, MemFlags::trusted(), state, 16);
+        let relative = builder.ins().isub(idx, low);
+
+        builder
+            .ins()
+            .icmp(IntCC::UnsignedGreaterThanOrEqual, relative, len)
+    }
+
+    /// multiply a number of cells by the size of a cell
+    fn scale(&self, builder: &mut FunctionBuilder, cells: Value) -> Value {
+        match self.config.cell_width.bytes() {
+            1 => cells,
+            bytes => builder.ins().ishl_imm(cells, bytes.trailing_zeros() as i64),
+        }
+    }
 
-                loops.push((start_block, after_block));
+    /// the address of the cell at the tape index `idx`
+    fn addr(&self, builder: &mut FunctionBuilder, idx: Value) -> Value {
+        let base = match self.guard {
+            Guard::Grow { state, .. } => {
+                builder
+                    .ins()
+                    .load(self.size_t, MemFlags::trusted(), state, 0)
             }
-            LoopEnd { .. } => {
-                let Some((start_block, after_block)) = loops.pop() else {
-                    unreachable!()
-                };
-                builder.ins().jump(start_block, &[]);
+            _ => builder.use_var(self.base),
+        };
+        let offset = self.scale(builder, idx);
+
+        builder.ins().iadd(base, offset)
+    }
+
+    /// the cell `offset` cells away from the pointer, making sure it's on the tape
+    fn place(&self, builder: &mut FunctionBuilder, offset: isize, span: Span) -> Place {
+        if self.direct() {
+            self.check_direct(builder, offset, None, span);
+            return self.direct_place(builder, offset);
+        }
+
+        let idx = self.index(builder, offset, span);
+        let addr = self.addr(builder, idx);
+        Place {
+            idx,
+            addr,
+            offset: 0,
+        }
+    }
+
+    /// the cell `offset` cells away from the pointer, which a multiply adds `count` times something
+    /// to. on a direct tape it only has to be on the tape when `count` isn't zero -- otherwise the
+    /// loop the multiply stands in for wouldn't have run, and the cell is left as it is, even if
+    /// it's in the padding around the tape.
+    fn target(
+        &self,
+        builder: &mut FunctionBuilder,
+        offset: isize,
+        count: Value,
+        span: Span,
+    ) -> Place {
+        if !self.direct() {
+            return self.place(builder, offset, span);
+        }
+
+        self.check_direct(builder, offset, Some(count), span);
+        self.direct_place(builder, offset)
+    }
 
-                builder.switch_to_block(after_block);
-                builder.seal_block(start_block);
-                builder.seal_block(after_block);
+    /// stop the program at `span` unless the pointer is on the tape -- only a direct pointer can
+    /// leave it (see `shift`)
+    fn check_pointer(&self, builder: &mut FunctionBuilder, span: Span) {
+        if self.direct() {
+            self.check_direct(builder, 0, None, span);
+        }
+    }
+
+    /// the cell under the pointer, once `check_pointer` has made sure it's on the tape
+    fn current(&self, builder: &mut FunctionBuilder) -> Place {
+        if self.direct() {
+            return self.direct_place(builder, 0);
+        }
+
+        let idx = builder.use_var(self.ptr);
+        let addr = self.addr(builder, idx);
+        Place {
+            idx,
+            addr,
+            offset: 0,
+        }
+    }
+
+    /// the cell `offset` cells away from a direct pointer, with the offset folded into loads and
+    /// stores
+    fn direct_place(&self, builder: &mut FunctionBuilder, offset: isize) -> Place {
+        let position = self.position(builder);
+        let idx = builder.ins().iadd_imm(position, offset as i64);
+        let ptr = builder.use_var(self.ptr);
+        let bytes = offset as i64 * self.config.cell_width.bytes() as i64;
+        match i32::try_from(bytes) {
+            Ok(bytes) => Place {
+                idx,
+                addr: ptr,
+                offset: bytes,
+            },
+            Err(_) => Place {
+                idx,
+                addr: builder.ins().iadd_imm(ptr, bytes),
+                offset: 0,
+            },
+        }
+    }
+
+    /// `place` again, after the tape might have grown and moved its cells around
+    fn settle(&self, builder: &mut FunctionBuilder, place: Place) -> Place {
+        match self.guard {
+            Guard::Grow { .. } => Place {
+                addr: self.addr(builder, place.idx),
+                ..place
+            },
+            _ => place,
+        }
+    }
+
+    /// the pointer was moved somewhere unknown, so none of the cells around it are known to be on
+    /// the tape anymore
+    fn forget(&self) {
+        self.checked.set(None);
+    }
+
+    /// move the pointer by `amount`. a direct pointer can end up off the tape -- that's only
+    /// stopped once a cell off it is touched.
+    fn shift(&self, builder: &mut FunctionBuilder, amount: isize, span: Span) {
+        if !self.direct() {
+            let idx = self.index(builder, amount, span);
+            builder.def_var(self.ptr, idx);
+            return;
+        }
+
+        let ptr = builder.use_var(self.ptr);
+        let bytes = amount as i64 * self.config.cell_width.bytes() as i64;
+        let moved = builder.ins().iadd_imm(ptr, bytes);
+        builder.def_var(self.ptr, moved);
+        // what was checked is now that much closer
+        if let Some((block, low, high)) = self.checked.get() {
+            self.checked.set(Some((block, low - amount, high - amount)));
+        }
+    }
+
+    /// skip to the returned block if the cell `value` is zero -- multiplies stand in for loops,
+    /// so their targets can only leave the tape when the loop would have run. only a growing
+    /// tape needs this, since `target` takes care of it on a direct one.
+    fn skip_if_zero(&self, builder: &mut FunctionBuilder, value: Value) -> Option<Block> {
+        if !matches!(self.guard, Guard::Grow { .. }) {
+            return None;
+        }
+
+        let body_block = builder.create_block();
+        let after_block = builder.create_block();
+        builder.ins().brif(value, body_block, &[], after_block, &[]);
+        builder.switch_to_block(body_block);
+        builder.seal_block(body_block);
+
+        Some(after_block)
+    }
+}
+
+/// join up with the block returned by `Tape::skip_if_zero`
+fn skipped(builder: &mut FunctionBuilder, after_block: Option<Block>) {
+    if let Some(after_block) = after_block {
+        builder.ins().jump(after_block, &[]);
+        builder.switch_to_block(after_block);
+        builder.seal_block(after_block);
+    }
+}
+
+/// stops the program when a cell overflows, for cells that don't wrap. cells are widened to 128
+/// bits for arithmetic, which is enough for any cell times any coefficient.
+struct Checked {
+    config: Config,
+    host: Host,
+}
+
+impl Checked {
+    /// the number in `cell`, widened to 128 bits
+    fn widen(&self, builder: &mut FunctionBuilder, cell: Value) -> Value {
+        match self.config.overflow {
+            Overflow::Signed => builder.ins().sextend(types::I128, cell),
+            Overflow::Wrap | Overflow::Unsigned => builder.ins().uextend(types::I128, cell),
+        }
+    }
+
+    fn constant(builder: &mut FunctionBuilder, value: i128) -> Value {
+        let low = builder.ins().iconst(types::I64, value as i64);
+        let high = builder.ins().iconst(types::I64, (value >> 64) as i64);
+
+        builder.ins().iconcat(low, high)
+    }
+
+    /// `cell + amount`, for the cell at tape index `idx`
+    fn add(
+        &self,
+        builder: &mut FunctionBuilder,
+        cell: Value,
+        amount: Value,
+        idx: Value,
+        span: Span,
+    ) -> Value {
+        let cell_t = builder.func.dfg.value_type(cell);
+        let wide = self.widen(builder, cell);
+        let sum = builder.ins().iadd(wide, amount);
+
+        let (min, max) = self.config.cell_range();
+        let min = Self::constant(builder, min);
+        let max = Self::constant(builder, max);
+        let above_min = builder
+            .ins()
+            .icmp(IntCC::SignedGreaterThanOrEqual, sum, min);
+        let below_max = builder.ins().icmp(IntCC::SignedLessThanOrEqual, sum, max);
+        let fits = builder.ins().band(above_min, below_max);
+        self.check(builder, fits, idx, sum, span);
+
+        builder.ins().ireduce(cell_t, sum)
+    }
+
+    /// the number in `cell` (at tape index `idx`), which is about to be counted down to zero by an
+    /// idiom standing in for a loop -- that overflows if it's negative
+    fn countdown(
+        &self,
+        builder: &mut FunctionBuilder,
+        cell: Value,
+        idx: Value,
+        span: Span,
+    ) -> Value {
+        let wide = self.widen(builder, cell);
+        if self.config.overflow == Overflow::Signed {
+            let positive = builder
+                .ins()
+                .icmp_imm(IntCC::SignedGreaterThanOrEqual, cell, 0);
+            // the loop would've gone below the smallest number first
+            let below_min = Self::constant(builder, self.config.cell_range().0 - 1);
+            self.check(builder, positive, idx, below_min, span);
+        }
+
+        wide
+    }
+
+    /// a multiply idiom -- the cell `offset` cells away from the pointer is counted down to zero,
+    /// adding it times each coefficient to the cells at the output offsets in `targets`
+    fn multiply(
+        &self,
+        builder: &mut FunctionBuilder,
+        tape: &Tape,
+        offset: isize,
+        targets: &[(isize, i64)],
+        span: Span,
+    ) {
+        let cell_t = Type::int(self.config.cell_width.bits() as u16).unwrap();
+        let place = tape.place(builder, offset, span);
+        let current_value = place.load(builder, cell_t);
+        let skip = tape.skip_if_zero(builder, current_value);
+        let count = self.countdown(builder, current_value, place.idx, span);
+
+        for &(output_offset, coefficient) in targets {
+            let coefficient = Self::constant(builder, coefficient as i128);
+            let mul_result = builder.ins().imul(count, coefficient);
+
+            let output = tape.target(builder, offset + output_offset, current_value, span);
+            let output_current_value = output.load(builder, cell_t);
+            let new_value = self.add(builder, output_current_value, mul_result, output.idx, span);
+            output.store(builder, new_value);
+        }
+        // growing the tape for the outputs moves the cells around
+        let place = tape.settle(builder, place);
+        let zero = builder.ins().iconst(cell_t, 0);
+        place.store(builder, zero);
+        skipped(builder, skip);
+    }
+
+    /// stop the program unless `ok`, blaming the cell at tape index `idx` for overflowing to
+    /// `value` (128 bits)
+    fn check(
+        &self,
+        builder: &mut FunctionBuilder,
+        ok: Value,
+        idx: Value,
+        value: Value,
+        span: Span,
+    ) {
+        let overflow_block = builder.create_block();
+        let ok_block = builder.create_block();
+        builder.set_cold_block(overflow_block);
+        builder.ins().brif(ok, ok_block, &[], overflow_block, &[]);
+
+        builder.switch_to_block(overflow_block);
+        builder.seal_block(overflow_block);
+        self.host
+            .stop(builder, Stop::Overflow, span, idx, Some(value));
+
+        builder.switch_to_block(ok_block);
+        builder.seal_block(ok_block);
+    }
+}
+
+/// why the generated code stopped the program
+#[derive(Clone, Copy)]
+#[repr(i64)]
+enum Stop {
+    OutOfBounds,
+    Overflow,
+    InputRanOut,
+    InfiniteLoop,
+    OutOfMemory,
+}
+
+impl Stop {
+    /// what stopped the program, blaming the cell at tape index `cell` -- `value` is the number an
+    /// overflowing cell overflowed to
+    fn fault(self, config: &Config, cell: isize, value: i128) -> Fault {
+        match self {
+            Stop::OutOfBounds => Fault::OutOfBounds {
+                cell,
+                tape_size: config.tape_size,
+            },
+            Stop::Overflow => {
+                let (min, max) = config.cell_range();
+                Fault::Overflow {
+                    cell,
+                    value,
+                    min,
+                    max,
+                }
             }
-            Input => {
-                let tape_ptr_value = builder.use_var(tape_ptr);
-                let tape_start = builder.ins().symbol_value(size_t, tape_id);
-                let (addr, _overflow) = builder.ins().uadd_overflow(tape_start, tape_ptr_value);
+            Stop::InputRanOut => Fault::InputRanOut,
+            Stop::InfiniteLoop => Fault::InfiniteLoop,
+            Stop::OutOfMemory => Fault::OutOfMemory { cell },
+        }
+    }
+}
+
+/// pack `span`'s start into one number for the callbacks
+fn pack(span: Span) -> i64 {
+    ((span.start.line as i64) << 32) | span.start.column as i64
+}
+
+/// the span `pack` was given
+fn unpack(span: u64) -> Span {
+    Span::at((span >> 32) as usize, (span & 0xffff_ffff) as usize)
+}
+
+/// everything the jitted code talks to, handed to it as its only argument
+struct Session<'a> {
+    config: Config,
+    input: Reader<'a>,
+    output: Writer<'a>,
+    fuel: Option<Fuel>,
+    /// what stopped the program early
+    error: Option<Error>,
+}
+
+impl Session<'_> {
+    fn fail(&mut self, fault: impl Into<Fault>, span: u64) {
+        self.error = Some(Error::Fault {
+            fault: fault.into(),
+            span: unpack(span),
+        });
+    }
+}
+
+// the callbacks below return something nonzero when they've stopped the program, after which the
+// jitted code returns right away
+
+/// `,` -- a byte, -1 once the input has run out, or -2 if reading failed
+extern "C" fn session_input(session: &mut Session, span: u64) -> i32 {
+    match session.output.flush().and_then(|()| session.input.read()) {
+        Ok(Some(byte)) => byte as i32,
+        Ok(None) => -1,
+        Err(err) => {
+            session.fail(err, span);
+            -2
+        }
+    }
+}
+
+/// `.`
+extern "C" fn session_output(session: &mut Session, byte: u8, span: u64) -> i32 {
+    match session.output.write(&[byte]) {
+        Ok(()) => 0,
+        Err(err) => {
+            session.fail(err, span);
+            1
+        }
+    }
+}
+
+/// constant output, `len` bytes at `data`
+extern "C" fn session_print(session: &mut Session, data: *const u8, len: usize, span: u64) -> i32 {
+    let bytes = unsafe { std::slice::from_raw_parts(data, len) };
+    match session.output.write(bytes) {
+        Ok(()) => 0,
+        Err(err) => {
+            session.fail(err, span);
+            1
+        }
+    }
+}
+
+/// get the output out before `#` prints to stderr
+extern "C" fn session_flush(session: &mut Session, span: u64) -> i32 {
+    match session.output.flush() {
+        Ok(()) => 0,
+        Err(err) => {
+            session.fail(err, span);
+            1
+        }
+    }
+}
+
+/// stop the program, blaming the cell at tape index `cell` -- for overflows, `low` and `high` are
+/// the halves of the number it overflowed to
+extern "C" fn session_fault(
+    session: &mut Session,
+    stop: Stop,
+    span: u64,
+    cell: i64,
+    low: u64,
+    high: u64,
+) {
+    let value = ((high as i128) << 64) | low as i128;
+    let fault = stop.fault(&session.config, cell as isize, value);
+    session.fail(fault, span);
+}
+
+/// hands out steps to the jit (see `Tank`), or 0 once the program is out of steps or time, which
+/// stops it in the loop at `span` just like the interpreter would stop it
+extern "C" fn session_refuel(session: &mut Session, span: u64) -> u64 {
+    let Some(fuel) = &mut session.fuel else {
+        unreachable!("only limited programs refuel")
+    };
+    match fuel.refuel() {
+        Ok(steps) => steps,
+        Err(limit) => {
+            session.error = Some(
+                Exhausted {
+                    limit,
+                    span: unpack(span),
+                    in_loop: true,
+                }
+                .into(),
+            );
+            0
+        }
+    }
+}
+
+/// what the generated code does i/o with, and how it stops the program
+#[derive(Clone, Copy)]
+enum Host {
+    /// the jit calls back into its `Session`, and returns to the caller to stop
+    Jit {
+        session: Value,
+        /// where the program goes to return
+        leave: Block,
+        input: FuncRef,
+        output: FuncRef,
+        print: FuncRef,
+        flush: FuncRef,
+        fault: FuncRef,
+        refuel: FuncRef,
+    },
+    /// executables use libc, and exit to stop
+    Libc {
+        size_t: Type,
+        getchar: FuncRef,
+        putchar: FuncRef,
+        fwrite: FuncRef,
+        fflush: FuncRef,
+        printf: FuncRef,
+        exit: FuncRef,
+        /// libc's `stdout` stream
+        stdout: GlobalValue,
+        /// a `printf` format for every `Stop` (see `message`)
+        messages: [GlobalValue; 5],
+        /// `-`, for numbers a cell overflowed to
+        minus: GlobalValue,
+        /// libgcc's 128-bit division, for printing those numbers
+        udivti3: Option<FuncRef>,
+        config: Config,
+    },
+}
+
+impl Host {
+    /// set up the host for the function in `builder`, which jumps to `leave` to return.
+    /// executables need the `messages` from `define_messages`.
+    fn new<M: Module>(
+        module: &mut M,
+        builder: &mut FunctionBuilder,
+        jit: bool,
+        session: Option<Value>,
+        leave: Block,
+        messages: Option<Messages>,
+    ) -> anyhow::Result<Self> {
+        let size_t = module.target_config().pointer_type();
+
+        if jit {
+            dlsym! { module, builder.func;
+                fn session_input(size_t, I64) -> I32;
+                fn session_output(size_t, I8, I64) -> I32;
+                fn session_print(size_t, size_t, size_t, I64) -> I32;
+                fn session_flush(size_t, I64) -> I32;
+                fn session_fault(size_t, I64, I64, I64, I64, I64);
+                fn session_refuel(size_t, I64) -> I64;
+            }
+
+            return Ok(Host::Jit {
+                session: session.expect("the jit gets a session"),
+                leave,
+                input: session_input,
+                output: session_output,
+                print: session_print,
+                flush: session_flush,
+                fault: session_fault,
+                refuel: session_refuel,
+            });
+        }
+
+        dlsym! { module, builder.func;
+            fn getchar() -> I32;
+            fn putchar(I8) -> I32;
+            fn fwrite(size_t, size_t, size_t, size_t) -> size_t;
+            fn fflush(size_t) -> I32;
+            fn printf(size_t, I64, I64, I64, I64, I64, I64, I64, I64) -> I32;
+            fn exit(I32);
+        }
+        let stdout_id = module.declare_data("stdout", Linkage::Import, false, false)?;
+        let stdout = module.declare_data_in_func(stdout_id, builder.func);
+        let messages = messages.expect("executables get messages");
+        let udivti3 = match messages.config.overflow {
+            Overflow::Wrap => None,
+            Overflow::Unsigned | Overflow::Signed => {
+                // 128-bit numbers are passed and returned in two registers, low half first
+                dlsym! { module, builder.func;
+                    fn __udivti3(I64, I64, I64, I64) -> I64, I64;
+                }
+                Some(__udivti3)
+            }
+        };
+
+        Ok(Host::Libc {
+            size_t,
+            getchar,
+            putchar,
+            fwrite,
+            fflush,
+            printf,
+            exit,
+            stdout,
+            messages: messages
+                .formats
+                .map(|format| module.declare_data_in_func(format, builder.func)),
+            minus: module.declare_data_in_func(messages.minus, builder.func),
+            udivti3,
+            config: messages.config,
+        })
+    }
+
+    /// return if `failed` (a callback's result) isn't zero
+    fn leave_if(builder: &mut FunctionBuilder, leave: Block, failed: Value) {
+        let next_block = builder.create_block();
+        builder.ins().brif(failed, leave, &[], next_block, &[]);
+        builder.switch_to_block(next_block);
+        builder.seal_block(next_block);
+    }
+
+    /// read a byte for `,` at `span` -- 32 bits, -1 once the input has run out
+    fn input(&self, builder: &mut FunctionBuilder, span: Span) -> Value {
+        match *self {
+            Host::Jit {
+                session,
+                leave,
+                input,
+                ..
+            } => {
+                let span = builder.ins().iconst(types::I64, pack(span));
+                let call = builder.ins().call(input, &[session, span]);
+                let byte = builder.inst_results(call)[0];
+                let failed = builder.ins().icmp_imm(IntCC::Equal, byte, -2);
+                Self::leave_if(builder, leave, failed);
+                byte
+            }
+            Host::Libc { getchar, .. } => {
                 let call = builder.ins().call(getchar, &[]);
-                let call_result = builder.inst_results(call)[0];
+                builder.inst_results(call)[0]
+            }
+        }
+    }
 
-                builder.ins().store(MemFlags::new(), call_result, addr, 0);
+    /// write `byte` (8 bits) for `.` at `span`
+    fn output(&self, builder: &mut FunctionBuilder, byte: Value, span: Span) {
+        match *self {
+            Host::Jit {
+                session,
+                leave,
+                output,
+                ..
+            } => {
+                let span = builder.ins().iconst(types::I64, pack(span));
+                let call = builder.ins().call(output, &[session, byte, span]);
+                let failed = builder.inst_results(call)[0];
+                Self::leave_if(builder, leave, failed);
             }
-            Output => {
-                let tape_ptr_value = builder.use_var(tape_ptr);
-                let tape_start = builder.ins().symbol_value(size_t, tape_id);
-                let (addr, _overflow) = builder.ins().uadd_overflow(tape_start, tape_ptr_value);
-                let current_value = builder.ins().load(types::I8, MemFlags::new(), addr, 0);
-                builder.ins().call(putchar, &[current_value]);
+            Host::Libc { putchar, .. } => {
+                builder.ins().call(putchar, &[byte]);
             }
-            Zero => {
-                let tape_ptr_value = builder.use_var(tape_ptr);
-                let tape_start = builder.ins().symbol_value(size_t, tape_id);
-                let (addr, _overflow) = builder.ins().uadd_overflow(tape_start, tape_ptr_value);
-                let zero = builder.ins().iconst(types::I8, 0);
+        }
+    }
 
-                builder.ins().store(MemFlags::new(), zero, addr, 0);
+    /// write `len` bytes from `data`
+    fn print(&self, builder: &mut FunctionBuilder, data: Value, len: Value, span: Span) {
+        match *self {
+            Host::Jit {
+                session,
+                leave,
+                print,
+                ..
+            } => {
+                let span = builder.ins().iconst(types::I64, pack(span));
+                let call = builder.ins().call(print, &[session, data, len, span]);
+                let failed = builder.inst_results(call)[0];
+                Self::leave_if(builder, leave, failed);
             }
-            Multiply {
-                amount,
-                output_offset,
+            Host::Libc {
+                size_t,
+                fwrite,
+                stdout,
+                ..
             } => {
-                let tape_ptr_value = builder.use_var(tape_ptr);
-                let tape_start = builder.ins().symbol_value(size_t, tape_id);
-                let (addr, _overflow) = builder.ins().uadd_overflow(tape_start, tape_ptr_value);
-                let current_value = builder.ins().load(types::I8, MemFlags::new(), addr, 0);
-                let zero = builder.ins().iconst(types::I8, 0);
-                builder.ins().store(MemFlags::new(), zero, addr, 0);
-
-                let amount_value = builder.ins().iconst(types::I8, amount as i64);
-                let (mul_result, _overflow) =
-                    builder.ins().umul_overflow(current_value, amount_value);
-
-                let output_addr = builder.ins().iadd_imm(addr, output_offset as i64);
-                let output_current_value =
-                    builder
-                        .ins()
-                        .load(types::I8, MemFlags::new(), output_addr, 0);
-                let (new_value, _overflow) = builder
-                    .ins()
-                    .uadd_overflow(output_current_value, mul_result);
+                let one = builder.ins().iconst(size_t, 1);
+                let stdout_ptr = builder.ins().symbol_value(size_t, stdout);
+                let stdout = builder.ins().load(size_t, MemFlags::new(), stdout_ptr, 0);
+                builder.ins().call(fwrite, &[data, one, len, stdout]);
+            }
+        }
+    }
 
-                builder
-                    .ins()
-                    .store(MemFlags::new(), new_value, output_addr, 0);
+    /// get everything written so far out
+    fn flush(&self, builder: &mut FunctionBuilder, span: Span) {
+        match *self {
+            Host::Jit {
+                session,
+                leave,
+                flush,
+                ..
+            } => {
+                let span = builder.ins().iconst(types::I64, pack(span));
+                let call = builder.ins().call(flush, &[session, span]);
+                let failed = builder.inst_results(call)[0];
+                Self::leave_if(builder, leave, failed);
             }
-            Move { output_offset } => {
-                let tape_ptr_value = builder.use_var(tape_ptr);
-                let tape_start = builder.ins().symbol_value(size_t, tape_id);
-                let (addr, _overflow) = builder.ins().uadd_overflow(tape_start, tape_ptr_value);
-                let current_value = builder.ins().load(types::I8, MemFlags::new(), addr, 0);
-                let zero = builder.ins().iconst(types::I8, 0);
-                builder.ins().store(MemFlags::new(), zero, addr, 0);
-
-                let output_addr = builder.ins().iadd_imm(addr, output_offset as i64);
-                let output_current_value =
-                    builder
-                        .ins()
-                        .load(types::I8, MemFlags::new(), output_addr, 0);
-                let (new_value, _overflow) = builder
-                    .ins()
-                    .uadd_overflow(output_current_value, current_value);
+            Host::Libc {
+                size_t,
+                fflush,
+                stdout,
+                ..
+            } => {
+                let stdout_ptr = builder.ins().symbol_value(size_t, stdout);
+                let stdout = builder.ins().load(size_t, MemFlags::new(), stdout_ptr, 0);
+                builder.ins().call(fflush, &[stdout]);
+            }
+        }
+    }
 
+    /// stop the program at `span`, blaming the cell at tape index `cell` -- overflows also get the
+    /// number it overflowed to (128 bits). this ends the current block.
+    fn stop(
+        &self,
+        builder: &mut FunctionBuilder,
+        stop: Stop,
+        span: Span,
+        cell: Value,
+        value: Option<Value>,
+    ) {
+        match *self {
+            Host::Jit {
+                session,
+                leave,
+                fault,
+                ..
+            } => {
+                let stop = builder.ins().iconst(types::I64, stop as i64);
+                let span = builder.ins().iconst(types::I64, pack(span));
+                let (low, high) = match value {
+                    Some(value) => builder.ins().isplit(value),
+                    None => {
+                        let zero = builder.ins().iconst(types::I64, 0);
+                        (zero, zero)
+                    }
+                };
                 builder
                     .ins()
-                    .store(MemFlags::new(), new_value, output_addr, 0);
+                    .call(fault, &[session, stop, span, cell, low, high]);
+                builder.ins().jump(leave, &[]);
             }
-            anchor_direction @ (AnchorRight | AnchorLeft) => {
-                // FIXME: wrap around on overflow (current behaviour: not found -> null ptr -> segfault)
-                let tape_ptr_value = builder.use_var(tape_ptr);
-                let tape_start = builder.ins().symbol_value(size_t, tape_id);
-                let (addr, _overflow) = builder.ins().uadd_overflow(tape_start, tape_ptr_value);
-                let current_value = builder.ins().load(types::I8, MemFlags::new(), addr, 0);
-                let one = builder.ins().iconst(types::I8, 1);
-
-                let (addr_dec, _overflow) = builder.ins().usub_overflow(addr, one);
-                builder.ins().store(MemFlags::new(), zero, addr_dec, 0);
-
-                let main_block = builder.create_block();
-                let short_circuit = builder.create_block();
-                builder
-                    .ins()
-                    .brif(current_value, main_block, &[], short_circuit, &[]);
-                builder.switch_to_block(main_block);
-                builder.seal_block(main_block);
-                let anchor_value = builder.ins().iconst(types::I8, 0xFF);
-                let max_ptr = builder.ins().iconst(types::I64, 0xFFFF);
-                let count = if anchor_direction == AnchorRight {
-                    builder.ins().isub(max_ptr, tape_ptr_value)
-                } else {
-                    tape_ptr_value
-                };
-                let call = builder.ins().call(
-                    if anchor_direction == AnchorRight {
-                        memchr
-                    } else {
-                        memrchr
-                    },
-                    &[addr, anchor_value, count],
-                );
-                let call_result = builder.inst_results(call)[0];
-                let zero = builder.ins().iconst(types::I8, 0);
-                builder.ins().store(MemFlags::new(), zero, call_result, 0);
-                let rel_call_result = builder.ins().isub(call_result, tape_start);
-                builder.def_var(tape_ptr, rel_call_result);
-
-                builder.switch_to_block(short_circuit);
-                builder.seal_block(short_circuit);
+            Host::Libc {
+                size_t,
+                printf,
+                exit,
+                messages,
+                config,
+                ..
+            } => {
+                let mut args = vec![builder.ins().symbol_value(size_t, messages[stop as usize])];
+                for slot in message(&config, stop).1 {
+                    match slot {
+                        Slot::Line => {
+                            args.push(builder.ins().iconst(types::I64, span.start.line as i64))
+                        }
+                        Slot::Column => {
+                            args.push(builder.ins().iconst(types::I64, span.start.column as i64))
+                        }
+                        Slot::Cell => args.push(cell),
+                        Slot::Value => {
+                            let value = value.expect("overflows have a value");
+                            args.extend(self.decimal(builder, value));
+                        }
+                    }
+                }
+                let zero = builder.ins().iconst(types::I64, 0);
+                args.resize(9, zero);
+                builder.ins().call(printf, &args);
+                let one = builder.ins().iconst(types::I32, 1);
+                builder.ins().call(exit, &[one]);
+                builder.ins().trap(TrapCode::unwrap_user(1));
             }
         }
     }
 
-    builder.ins().return_(&[]);
+    /// the arguments for `%.*s%.0lu%0*lu` that print the 128-bit `value` in a fault message --
+    /// whether there's a minus sign, the minus sign, and the digits of the number without its sign
+    /// split in two, with the first half left out when it's zero
+    fn decimal(&self, builder: &mut FunctionBuilder, value: Value) -> [Value; 5] {
+        let Host::Libc {
+            size_t,
+            minus,
+            udivti3: Some(udivti3),
+            ..
+        } = *self
+        else {
+            unreachable!("only executables that check for overflow print numbers")
+        };
+
+        let zero_high = builder.ins().iconst(types::I64, 0);
+        let zero = builder.ins().sextend(types::I128, zero_high);
+        let negative = builder.ins().icmp(IntCC::SignedLessThan, value, zero);
+        let negated = builder.ins().ineg(value);
+        // the most negative number negates to itself, which is right when read as unsigned
+        let magnitude = builder.ins().select(negative, negated, value);
+
+        let split = builder
+            .ins()
+            .iconst(types::I64, 10_000_000_000_000_000_000u64 as i64);
+        let (magnitude_low, magnitude_high) = builder.ins().isplit(magnitude);
+        let call = builder
+            .ins()
+            .call(udivti3, &[magnitude_low, magnitude_high, split, zero_high]);
+        let high = match builder.inst_results(call) {
+            &[low, high] => builder.ins().iconcat(low, high),
+            _ => unreachable!(),
+        };
+        let split = builder.ins().uextend(types::I128, split);
+        let rest = builder.ins().imul(high, split);
+        let low = builder.ins().isub(magnitude, rest);
+        let high = builder.ins().ireduce(types::I64, high);
+        let low = builder.ins().ireduce(types::I64, low);
+
+        let signs = builder.ins().uextend(types::I64, negative);
+        let minus = builder.ins().symbol_value(size_t, minus);
+        let has_high = builder.ins().icmp_imm(IntCC::NotEqual, high, 0);
+        let padded = builder.ins().iconst(types::I64, 19);
+        let unpadded = builder.ins().iconst(types::I64, 0);
+        let width = builder.ins().select(has_high, padded, unpadded);
+
+        [signs, minus, high, width, low]
+    }
+
+    /// stop the program at `span` if `cond`, blaming the cell at tape index `cell`
+    fn stop_if(
+        &self,
+        builder: &mut FunctionBuilder,
+        cond: Value,
+        stop: Stop,
+        span: Span,
+        cell: Value,
+    ) {
+        let stop_block = builder.create_block();
+        let next_block = builder.create_block();
+        builder.set_cold_block(stop_block);
+        builder.ins().brif(cond, stop_block, &[], next_block, &[]);
+
+        builder.switch_to_block(stop_block);
+        builder.seal_block(stop_block);
+        self.stop(builder, stop, span, cell, None);
+
+        builder.switch_to_block(next_block);
+        builder.seal_block(next_block);
+    }
+}
+
+/// counts down the steps left for `--max-steps` and `--timeout` -- loops burn one every time they
+/// go around, and ask the session for more once there are none left
+struct Tank {
+    steps: Variable,
+    host: Host,
+}
+
+impl Tank {
+    /// burn a step on the way back to the start of the loop at `span`
+    fn burn(&self, builder: &mut FunctionBuilder, span: Span) {
+        let Host::Jit {
+            session,
+            leave,
+            refuel,
+            ..
+        } = self.host
+        else {
+            unreachable!("only the jit can be limited")
+        };
+
+        let refuel_block = builder.create_block();
+        let burn_block = builder.create_block();
+        builder.set_cold_block(refuel_block);
+        let steps = builder.use_var(self.steps);
+        builder
+            .ins()
+            .brif(steps, burn_block, &[], refuel_block, &[]);
+
+        builder.switch_to_block(refuel_block);
+        builder.seal_block(refuel_block);
+        let span = builder.ins().iconst(types::I64, pack(span));
+        let call = builder.ins().call(refuel, &[session, span]);
+        let steps = builder.inst_results(call)[0];
+        builder.def_var(self.steps, steps);
+        builder.ins().brif(steps, burn_block, &[], leave, &[]);
+
+        builder.switch_to_block(burn_block);
+        builder.seal_block(burn_block);
+        let steps = builder.use_var(self.steps);
+        let steps = builder.ins().iadd_imm(steps, -1);
+        builder.def_var(self.steps, steps);
+    }
+}
+
+/// define a read-only data object holding `bytes`
+fn define_bytes<M: Module>(module: &mut M, name: &str, bytes: Vec<u8>) -> anyhow::Result<DataId> {
+    let mut data_description = DataDescription::new();
+    data_description.define(bytes.into_boxed_slice());
+    let data_id = module.declare_data(name, Linkage::Local, false, false)?;
+    module.define_data(data_id, &data_description)?;
+
+    Ok(data_id)
+}
+
+/// where the numbers go in a fault message in an executable (see `message`)
+#[derive(Clone, Copy)]
+enum Slot {
+    Line,
+    Column,
+    Cell,
+    /// the number a cell overflowed to, in 128 bits (see `Host::decimal`)
+    Value,
+}
+
+/// the `printf` format an executable stops the program with for `stop`, and the numbers it wants
+/// in order. it's the same message the cli prints when the jit or the interpreters stop for the
+/// same reason, rendered with stand-ins for the numbers that are only known at runtime.
+fn message(config: &Config, stop: Stop) -> (String, Vec<Slot>) {
+    // numbers that don't show up in a message otherwise
+    const LINE: usize = 4_242_424_201;
+    const COLUMN: usize = 4_242_424_202;
+    const CELL: isize = -4_242_424_203;
+    const VALUE: i128 = -4_242_424_204;
+
+    let error = Error::Fault {
+        fault: stop.fault(config, CELL, VALUE),
+        span: Span::at(LINE, COLUMN),
+    };
+    // the same as the cli's `fatal`
+    let rendered = format!("! fatal error: {error}\n")
+        .to_lowercase()
+        .replace('%', "%%");
+
+    let mut stand_ins = [
+        (LINE.to_string(), Slot::Line, "%lu"),
+        (COLUMN.to_string(), Slot::Column, "%lu"),
+        (CELL.to_string(), Slot::Cell, "%ld"),
+        (VALUE.to_string(), Slot::Value, "%.*s%.0lu%0*lu"),
+    ]
+    .into_iter()
+    .filter_map(|(text, slot, placeholder)| {
+        let at = rendered.find(&text)?;
+        Some((at, text.len(), slot, placeholder))
+    })
+    .collect::<Vec<_>>();
+    stand_ins.sort_by_key(|&(at, ..)| at);
+
+    let mut format = String::new();
+    let mut slots = Vec::new();
+    let mut end = 0;
+    for (at, len, slot, placeholder) in stand_ins {
+        format.push_str(&rendered[end..at]);
+        format.push_str(placeholder);
+        slots.push(slot);
+        end = at + len;
+    }
+    format.push_str(&rendered[end..]);
+    format.push('\0');
+
+    (format, slots)
+}
+
+/// the data `Host::Libc` stops the program with
+#[derive(Clone, Copy)]
+struct Messages {
+    /// a `printf` format for every `Stop`
+    formats: [DataId; 5],
+    /// `-`, for `Host::decimal`
+    minus: DataId,
+    config: Config,
+}
+
+/// define the `Messages` executables stop the program with
+fn define_messages<M: Module>(module: &mut M, config: Config) -> anyhow::Result<Messages> {
+    let stops = [
+        Stop::OutOfBounds,
+        Stop::Overflow,
+        Stop::InputRanOut,
+        Stop::InfiniteLoop,
+        Stop::OutOfMemory,
+    ];
+    let mut formats = Vec::new();
+    for stop in stops {
+        let (format, _) = message(&config, stop);
+        let name = format!("message{}", stop as usize);
+        formats.push(define_bytes(module, &name, format.into_bytes())?);
+    }
+
+    Ok(Messages {
+        formats: formats.try_into().unwrap(),
+        minus: define_bytes(module, "minus", b"-".to_vec())?,
+        config,
+    })
+}
+
+/// define `grow(state, idx)`, which grows the tape described by `state` (the address of cell 0,
+/// the lowest index on the tape, and its length) until it has the cell at `idx`, at least doubling
+/// it. cells keep their indices. growing a tape with no memory allocates the first one. it returns
+/// 1 if there isn't enough memory, leaving the tape as it was, so the caller can stop the program.
+fn define_grow<M: Module>(module: &mut M, config: Config) -> anyhow::Result<FuncId> {
+    let mut ctx = module.make_context();
+
+    let size_t = module.target_config().pointer_type();
+    let cell_bytes = config.cell_width.bytes() as i64;
+    ctx.func.signature.params.push(AbiParam::new(size_t));
+    ctx.func.signature.params.push(AbiParam::new(size_t));
+    ctx.func.signature.returns.push(AbiParam::new(types::I8));
+
+    let mut builder_context = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut ctx.func, &mut builder_context);
+    dlsym! { module, builder.func;
+        fn calloc(size_t, size_t) -> size_t;
+        fn free(size_t);
+    }
+
+    let entry_block = builder.create_block();
+    let copy_block = builder.create_block();
+    let failed_block = builder.create_block();
+    builder.set_cold_block(failed_block);
+    builder.append_block_params_for_function_params(entry_block);
+    builder.switch_to_block(entry_block);
+    builder.seal_block(entry_block);
+    let (state, idx) = match builder.block_params(entry_block) {
+        &[state, idx] => (state, idx),
+        _ => unreachable!(),
+    };
+
+    let base = builder.ins().load(size_t, MemFlags::trusted(), state, 0);
+    let low = builder.ins().load(size_t, MemFlags::trusted(), state, 8);
+    let len = builder.ins().load(size_t, MemFlags::trusted(), state, 16);
+    let start = builder.ins().imul_imm(low, cell_bytes);
+    let start = builder.ins().iadd(base, start);
+    let high = builder.ins().iadd(low, len);
+
+    // growing to the left, the cells to the right stay put
+    let doubled_low = builder.ins().isub(low, len);
+    let past_low = builder.ins().icmp(IntCC::SignedLessThan, idx, doubled_low);
+    let left_low = builder.ins().select(past_low, idx, doubled_low);
+    // and the other way around
+    let doubled_high = builder.ins().iadd(high, len);
+    let idx_end = builder.ins().iadd_imm(idx, 1);
+    let past_high = builder
+        .ins()
+        .icmp(IntCC::SignedGreaterThan, idx_end, doubled_high);
+    let right_high = builder.ins().select(past_high, idx_end, doubled_high);
+
+    let left = builder.ins().icmp(IntCC::SignedLessThan, idx, low);
+    let new_low = builder.ins().select(left, left_low, low);
+    let new_high = builder.ins().select(left, high, right_high);
+    let new_len = builder.ins().isub(new_high, new_low);
+    let cell_size = builder.ins().iconst(size_t, cell_bytes);
+    let call = builder.ins().call(calloc, &[new_len, cell_size]);
+    let new_start = builder.inst_results(call)[0];
+    builder
+        .ins()
+        .brif(new_start, copy_block, &[], failed_block, &[]);
+
+    builder.switch_to_block(copy_block);
+    builder.seal_block(copy_block);
+    let moved = builder.ins().isub(low, new_low);
+    let moved = builder.ins().imul_imm(moved, cell_bytes);
+    let dest = builder.ins().iadd(new_start, moved);
+    let size = builder.ins().imul_imm(len, cell_bytes);
+    builder.call_memcpy(module.target_config(), dest, start, size);
+    builder.ins().call(free, &[start]);
+    let new_base = builder.ins().imul_imm(new_low, cell_bytes);
+    let new_base = builder.ins().isub(new_start, new_base);
+    builder.ins().store(MemFlags::trusted(), new_base, state, 0);
+    builder.ins().store(MemFlags::trusted(), new_low, state, 8);
+    builder.ins().store(MemFlags::trusted(), new_len, state, 16);
+    let grown = builder.ins().iconst(types::I8, 0);
+    builder.ins().return_(&[grown]);
+
+    builder.switch_to_block(failed_block);
+    builder.seal_block(failed_block);
+    let failed = builder.ins().iconst(types::I8, 1);
+    builder.ins().return_(&[failed]);
+
     builder.finalize();
 
-    let id = module.declare_function("main", Linkage::Export, &ctx.func.signature)?;
+    let id = module.declare_function("grow", Linkage::Local, &ctx.func.signature)?;
     module.define_function(id, &mut ctx)?;
     module.clear_context(&mut ctx);
 
     Ok(id)
 }
+
+/// with `split_loops`, loops with at least this many instructions get a function of their own
+/// wherever they are, not just at the top level
+const LARGE_LOOP: usize = 256;
+
+/// what a function for a loop returns instead of the pointer when it stopped the program -- no
+/// address or index gets anywhere near it
+const STOPPED: i64 = i64::MIN;
+
+/// compile `ir` into `main`. with `jit`, `main` takes the address of a `Session` to do i/o with,
+/// and otherwise it's a C `int main(void)` that uses libc. with `split_loops`, loops are split off
+/// into functions of their own (see `Codegen::split`).
+fn go<M: Any + Module>(
+    mut ir: Vec<Node>,
+    module: &mut M,
+    config: Config,
+    input: Option<Vec<u8>>,
+    jit: bool,
+    split_loops: bool,
+) -> anyhow::Result<FuncId> {
+    let limited = config.max_steps.is_some() || config.timeout.is_some();
+    if limited && !jit {
+        anyhow::bail!("`--max-steps` and `--timeout` only work with `--jit`");
+    }
+
+    // a growing tape lives on the heap, so `grow` has to exist before anything is finalized
+    let grow = match config.bounds {
+        Bounds::Grow => Some(define_grow(module, config)?),
+        _ => None,
+    };
+
+    // how far multiplies reach past the cell they count down, which is on the tape -- that's how
+    // much padding a direct tape needs
+    let margin = match config.bounds {
+        Bounds::Abort => ir
+            .iter()
+            .flat_map(|node| match &node.inst {
+                Multiply { output_offset, .. } | Move { output_offset, .. } => vec![*output_offset],
+                MultiplyAdd { targets, .. } => targets
+                    .iter()
+                    .map(|&(output_offset, _)| output_offset)
+                    .collect(),
+                _ => Vec::new(),
+            })
+            .map(isize::unsigned_abs)
+            .max()
+            .unwrap_or(0),
+        _ => 0,
+    };
+
+    let tape = match grow {
+        Some(_) => None,
+        None => {
+            let mut data_description = DataDescription::new();
+            data_description
+                .define_zeroinit((config.tape_size + 2 * margin) * config.cell_width.bytes());
+            let tape_id = module.declare_data("tape", Linkage::Export, true, false)?;
+            module.define_data(tape_id, &data_description)?;
+            if let Some(jit_module) = (module as &mut dyn Any).downcast_mut::<JITModule>() {
+                jit_module.finalize_definitions()?;
+            }
+
+            Some(tape_id)
+        }
+    };
+
+    let input = match input {
+        Some(mut bytes) => {
+            let len = bytes.len() as i64;
+            bytes.push(0);
+            let data_id = define_bytes(module, "input", bytes)?;
+
+            let mut data_description = DataDescription::new();
+            data_description.define_zeroinit(8);
+            let position_id = module.declare_data("input_position", Linkage::Local, true, false)?;
+            module.define_data(position_id, &data_description)?;
+
+            Some((data_id, position_id, len))
+        }
+        None => None,
+    };
+
+    let messages = match jit {
+        true => None,
+        false => Some(define_messages(module, config)?),
+    };
+
+    // splitting loops off needs to know where they end
+    if split_loops {
+        ir::match_brackets(&mut ir);
+    }
+
+    let mut codegen = Codegen {
+        module,
+        config,
+        jit,
+        limited,
+        split_loops,
+        grow,
+        tape,
+        margin,
+        input,
+        messages,
+        data_count: 0,
+        loop_count: 0,
+    };
+
+    codegen.function(&ir, 0..ir.len(), true)
+}
+
+/// what the functions a program is compiled into share
+struct Codegen<'a, M> {
+    module: &'a mut M,
+    config: Config,
+    jit: bool,
+    /// whether the program has `--max-steps` or `--timeout` (see `Tank`)
+    limited: bool,
+    split_loops: bool,
+    grow: Option<FuncId>,
+    /// the tape, unless it grows
+    tape: Option<DataId>,
+    /// the cells of padding on either side of a direct tape, for the targets of multiplies whose
+    /// loops wouldn't have run
+    margin: usize,
+    /// the input baked in for `,` (only in executables -- the jit's reader has it) -- its bytes
+    /// (with an extra one so reading past the end is harmless), how many have been read and how
+    /// many there are
+    input: Option<(DataId, DataId, i64)>,
+    /// a `printf` format for every `Stop`, in executables
+    messages: Option<Messages>,
+    /// constant output and tape images so far
+    data_count: usize,
+    /// functions split off for loops so far
+    loop_count: usize,
+}
+
+impl<M: Module> Codegen<'_, M> {
+    /// whether a loop from `start` to `end` (`top_level` or not) gets a function of its own --
+    /// that keeps the functions cranelift compiles small, which is easier on its register
+    /// allocator, at the cost of a call every time the loop is reached
+    fn split(&self, top_level: bool, start: usize, end: usize) -> bool {
+        self.split_loops && (top_level || end - start >= LARGE_LOOP)
+    }
+
+    /// define `bytes` as constant data and make it usable in `func`
+    fn data(&mut self, func: &mut Function, bytes: Vec<u8>) -> anyhow::Result<GlobalValue> {
+        let data_id = define_bytes(self.module, &format!("data{}", self.data_count), bytes)?;
+        self.data_count += 1;
+
+        Ok(self.module.declare_data_in_func(data_id, func))
+    }
+
+    /// a function for a loop takes the pointer where its caller left it (along with the session,
+    /// a growing tape's state and the steps left, where there are any), and returns where it
+    /// left the pointer (or `STOPPED`) and the steps left
+    fn loop_signature(&self) -> Signature {
+        let size_t = self.module.target_config().pointer_type();
+        let mut sig = self.module.make_signature();
+        if self.jit {
+            sig.params.push(AbiParam::new(size_t));
+        }
+        sig.params.push(AbiParam::new(size_t));
+        sig.returns.push(AbiParam::new(size_t));
+        if self.grow.is_some() {
+            sig.params.push(AbiParam::new(size_t));
+        }
+        if self.limited {
+            sig.params.push(AbiParam::new(types::I64));
+            sig.returns.push(AbiParam::new(types::I64));
+        }
+
+        sig
+    }
+
+    /// compile the instructions in `body` into `main`, or into a function for the loop `body` is
+    fn function(&mut self, ir: &[Node], body: Range<usize>, main: bool) -> anyhow::Result<FuncId> {
+        let config = self.config;
+        let size_t = self.module.target_config().pointer_type();
+        let width = config.cell_width;
+        let cell_t = Type::int(width.bits() as u16).unwrap();
+
+        let mut ctx = self.module.make_context();
+        match main {
+            true if self.jit => ctx.func.signature.params.push(AbiParam::new(size_t)),
+            true => ctx.func.signature.returns.push(AbiParam::new(types::I32)),
+            false => ctx.func.signature = self.loop_signature(),
+        }
+        let mut builder_context = FunctionBuilderContext::new();
+        let mut builder = FunctionBuilder::new(&mut ctx.func, &mut builder_context);
+        let entry_block = builder.create_block();
+        let leave_block = builder.create_block();
+        builder.append_block_params_for_function_params(entry_block);
+        builder.switch_to_block(entry_block);
+        builder.seal_block(entry_block);
+        let mut params = builder.block_params(entry_block).to_vec().into_iter();
+        let session = self.jit.then(|| params.next().unwrap());
+        let host = Host::new(
+            self.module,
+            &mut builder,
+            self.jit,
+            session,
+            leave_block,
+            self.messages,
+        )?;
+        // a loop carries on from where its caller left the pointer
+        let resumed = (!main).then(|| params.next().unwrap());
+        let tape_ptr = Variable::from_u32(0);
+        builder.declare_var(tape_ptr, size_t);
+        let tape_base = Variable::from_u32(1);
+        builder.declare_var(tape_base, size_t);
+
+        // initialize tape
+        let guard = if let Some(grow_id) = self.grow {
+            let grow = self.module.declare_func_in_func(grow_id, builder.func);
+            // `main` sets up the tape that its loops get handed
+            let state = match params.next() {
+                Some(state) => state,
+                None => {
+                    let slot = builder.create_sized_stack_slot(StackSlotData::new(
+                        StackSlotKind::ExplicitSlot,
+                        24,
+                        3,
+                    ));
+                    let state = builder.ins().stack_addr(size_t, slot, 0);
+
+                    // start with an empty tape and grow it to the right size
+                    let zero = builder.ins().iconst(size_t, 0);
+                    for offset in [0, 8, 16] {
+                        builder
+                            .ins()
+                            .store(MemFlags::trusted(), zero, state, offset);
+                    }
+                    let last = builder.ins().iconst(size_t, config.tape_size as i64 - 1);
+                    let call = builder.ins().call(grow, &[state, last]);
+                    let failed = builder.inst_results(call)[0];
+                    let span = ir.first().map_or(Span::at(1, 1), |node| node.span);
+                    host.stop_if(&mut builder, failed, Stop::OutOfMemory, span, last);
+
+                    state
+                }
+            };
+
+            Guard::Grow { grow, state }
+        } else {
+            let tape_id = self
+                .module
+                .declare_data_in_func(self.tape.unwrap(), builder.func);
+            if self.jit {
+                // big tapes get their own mapping, too far from the code for pc-relative addressing
+                if let GlobalValueData::Symbol { colocated, .. } =
+                    &mut builder.func.global_values[tape_id]
+                {
+                    *colocated = false;
+                }
+            }
+            let tape_data = builder.ins().symbol_value(size_t, tape_id);
+            let tape_start = builder
+                .ins()
+                .iadd_imm(tape_data, (self.margin * width.bytes()) as i64);
+            builder.def_var(tape_base, tape_start);
+
+            match config.bounds {
+                Bounds::Abort => Guard::Abort,
+                _ => Guard::Wrap,
+            }
+        };
+        let start = match (resumed, &guard) {
+            (Some(ptr), _) => ptr,
+            // the pointer starts out on cell 0 (see `Tape::direct`)
+            (None, Guard::Abort) => builder.use_var(tape_base),
+            (None, _) => builder.ins().iconst(size_t, 0),
+        };
+        builder.def_var(tape_ptr, start);
+        let tape = Tape {
+            config,
+            size_t,
+            ptr: tape_ptr,
+            base: tape_base,
+            guard,
+            host,
+            checked: std::cell::Cell::new(None),
+        };
+
+        let checked = match config.overflow {
+            Overflow::Wrap => None,
+            Overflow::Unsigned | Overflow::Signed => Some(Checked { config, host }),
+        };
+
+        let inline_input = self.input.map(|(data_id, position_id, len)| {
+            (
+                self.module.declare_data_in_func(data_id, builder.func),
+                self.module.declare_data_in_func(position_id, builder.func),
+                len,
+            )
+        });
+
+        // `dprintf` for `#`, which gets the pointer and every cell at once
+        let dprintf = match ir[body.clone()].iter().any(|node| node.inst == DebugDump) {
+            true => {
+                let mut sig = self.module.make_signature();
+                sig.params.push(AbiParam::new(types::I32));
+                sig.params.push(AbiParam::new(size_t));
+                sig.params
+                    .extend([AbiParam::new(types::I64); DUMP_CELLS + 1]);
+                sig.returns.push(AbiParam::new(types::I32));
+                let dprintf_id = self
+                    .module
+                    .declare_function("dprintf", Linkage::Import, &sig)?;
+                Some(self.module.declare_func_in_func(dprintf_id, builder.func))
+            }
+            false => None,
+        };
+
+        let tank = match self.limited {
+            true => {
+                let steps = Variable::from_u32(2);
+                builder.declare_var(steps, types::I64);
+                let left = match params.next() {
+                    Some(left) => left,
+                    None => builder.ins().iconst(types::I64, 0),
+                };
+                builder.def_var(steps, left);
+
+                Some(Tank { steps, host })
+            }
+            false => None,
+        };
+
+        let mut loops = Vec::new();
+
+        let mut idx = body.start;
+        while idx < body.end {
+            let Node { inst, span } = ir[idx].clone();
+            idx += 1;
+
+            match inst {
+                Shift { amount } => tape.shift(&mut builder, amount, span),
+                Arithmetic { amount, offset } => {
+                    let place = tape.place(&mut builder, offset, span);
+                    let current_value = place.load(&mut builder, cell_t);
+                    let new_value = match &checked {
+                        Some(checked) => {
+                            let amount = Checked::constant(&mut builder, amount as i128);
+                            checked.add(&mut builder, current_value, amount, place.idx, span)
+                        }
+                        None => builder
+                            .ins()
+                            .iadd_imm(current_value, width.wrap_signed(amount)),
+                    };
+                    place.store(&mut builder, new_value);
+                }
+                // the loop's function has the whole loop in it, so its own `[` isn't split off again
+                LoopStart { end_index }
+                    if idx - 1 != body.start
+                        && self.split(main && loops.is_empty(), idx - 1, end_index) =>
+                {
+                    let id = self.function(ir, idx - 1..end_index + 1, false)?;
+                    let func = self.module.declare_func_in_func(id, builder.func);
+                    let mut args = Vec::from_iter(session);
+                    args.push(builder.use_var(tape_ptr));
+                    if let Guard::Grow { state, .. } = tape.guard {
+                        args.push(state);
+                    }
+                    if let Some(tank) = &tank {
+                        args.push(builder.use_var(tank.steps));
+                    }
+                    let call = builder.ins().call(func, &args);
+                    let results = builder.inst_results(call).to_vec();
+                    if self.jit {
+                        let stopped = builder.ins().icmp_imm(IntCC::Equal, results[0], STOPPED);
+                        Host::leave_if(&mut builder, leave_block, stopped);
+                    }
+                    builder.def_var(tape_ptr, results[0]);
+                    if let Some(tank) = &tank {
+                        builder.def_var(tank.steps, results[1]);
+                    }
+                    tape.forget();
+
+                    idx = end_index + 1;
+                }
+                LoopStart { .. } => {
+                    let start_block = builder.create_block();
+                    let body_block = builder.create_block();
+                    let after_block = builder.create_block();
+
+                    // `]` checks the pointer before coming back around
+                    tape.check_pointer(&mut builder, span);
+                    builder.ins().jump(start_block, &[]);
+                    builder.switch_to_block(start_block);
+                    let current_value = tape.current(&mut builder).load(&mut builder, cell_t);
+                    builder
+                        .ins()
+                        .brif(current_value, body_block, &[], after_block, &[]);
+
+                    builder.switch_to_block(body_block);
+                    builder.seal_block(body_block);
+
+                    loops.push((start_block, after_block, span));
+                }
+                LoopEnd { .. } => {
+                    let Some((start_block, after_block, start_span)) = loops.pop() else {
+                        unreachable!()
+                    };
+                    if let Some(tank) = &tank {
+                        tank.burn(&mut builder, start_span);
+                    }
+                    tape.check_pointer(&mut builder, span);
+                    builder.ins().jump(start_block, &[]);
+
+                    builder.switch_to_block(after_block);
+                    builder.seal_block(start_block);
+                    builder.seal_block(after_block);
+                }
+                Input { offset } => {
+                    let place = tape.place(&mut builder, offset, span);
+                    let call_result = match inline_input {
+                        Some((data, position, len)) => {
+                            let position_addr = builder.ins().symbol_value(size_t, position);
+                            let read =
+                                builder
+                                    .ins()
+                                    .load(size_t, MemFlags::trusted(), position_addr, 0);
+                            let in_range =
+                                builder.ins().icmp_imm(IntCC::UnsignedLessThan, read, len);
+                            let data = builder.ins().symbol_value(size_t, data);
+                            let byte_addr = builder.ins().iadd(data, read);
+                            let byte =
+                                builder
+                                    .ins()
+                                    .uload8(types::I32, MemFlags::trusted(), byte_addr, 0);
+                            let advance = builder.ins().uextend(size_t, in_range);
+                            let read = builder.ins().iadd(read, advance);
+                            builder
+                                .ins()
+                                .store(MemFlags::trusted(), read, position_addr, 0);
+                            let minus_one = builder.ins().iconst(types::I32, -1);
+                            builder.ins().select(in_range, byte, minus_one)
+                        }
+                        None => host.input(&mut builder, span),
+                    };
+                    let is_eof = builder.ins().icmp_imm(IntCC::Equal, call_result, -1);
+                    let byte = match width {
+                        CellWidth::W8 | CellWidth::W16 => {
+                            builder.ins().ireduce(cell_t, call_result)
+                        }
+                        CellWidth::W32 => call_result,
+                        CellWidth::W64 => builder.ins().uextend(cell_t, call_result),
+                    };
+
+                    let eof_value = match config.eof {
+                        Eof::Zero => builder.ins().iconst(cell_t, 0),
+                        Eof::MinusOne => builder.ins().iconst(cell_t, -1),
+                        Eof::Unchanged => place.load(&mut builder, cell_t),
+                        Eof::Error => {
+                            let eof_block = builder.create_block();
+                            let read_block = builder.create_block();
+                            builder.set_cold_block(eof_block);
+                            builder.ins().brif(is_eof, eof_block, &[], read_block, &[]);
+
+                            builder.switch_to_block(eof_block);
+                            builder.seal_block(eof_block);
+                            let ptr = tape.position(&mut builder);
+                            host.stop(&mut builder, Stop::InputRanOut, span, ptr, None);
+
+                            builder.switch_to_block(read_block);
+                            builder.seal_block(read_block);
+                            byte
+                        }
+                    };
+                    let value = builder.ins().select(is_eof, eof_value, byte);
+
+                    place.store(&mut builder, value);
+                }
+                Output { offset } => {
+                    let place = tape.place(&mut builder, offset, span);
+                    let mut current_value = place.load(&mut builder, cell_t);
+                    if width != CellWidth::W8 {
+                        current_value = builder.ins().ireduce(types::I8, current_value);
+                    }
+                    host.output(&mut builder, current_value, span);
+                }
+                Zero { offset } => {
+                    let place = tape.place(&mut builder, offset, span);
+                    if let Some(checked) = &checked {
+                        let current_value = place.load(&mut builder, cell_t);
+                        checked.countdown(&mut builder, current_value, place.idx, span);
+                    }
+                    let zero = builder.ins().iconst(cell_t, 0);
+
+                    place.store(&mut builder, zero);
+                }
+                Set { value, offset } => {
+                    let place = tape.place(&mut builder, offset, span);
+                    let value = builder.ins().iconst(cell_t, value as i64);
+
+                    place.store(&mut builder, value);
+                }
+                Multiply {
+                    amount,
+                    offset,
+                    output_offset,
+                } => {
+                    if let Some(checked) = &checked {
+                        checked.multiply(
+                            &mut builder,
+                            &tape,
+                            offset,
+                            &[(output_offset, amount)],
+                            span,
+                        );
+                        continue;
+                    }
+
+                    let place = tape.place(&mut builder, offset, span);
+                    let current_value = place.load(&mut builder, cell_t);
+                    let skip = tape.skip_if_zero(&mut builder, current_value);
+
+                    let amount_value = builder.ins().iconst(cell_t, width.wrap(amount) as i64);
+                    let (mul_result, _overflow) =
+                        builder.ins().umul_overflow(current_value, amount_value);
+
+                    let output =
+                        tape.target(&mut builder, offset + output_offset, current_value, span);
+                    let output_current_value = output.load(&mut builder, cell_t);
+                    let (new_value, _overflow) = builder
+                        .ins()
+                        .uadd_overflow(output_current_value, mul_result);
+
+                    output.store(&mut builder, new_value);
+                    // growing the tape for the output moves the cells around
+                    let place = tape.settle(&mut builder, place);
+                    let zero = builder.ins().iconst(cell_t, 0);
+                    place.store(&mut builder, zero);
+                    skipped(&mut builder, skip);
+                }
+                Move {
+                    offset,
+                    output_offset,
+                } => {
+                    if let Some(checked) = &checked {
+                        checked.multiply(&mut builder, &tape, offset, &[(output_offset, 1)], span);
+                        continue;
+                    }
+
+                    let place = tape.place(&mut builder, offset, span);
+                    let current_value = place.load(&mut builder, cell_t);
+                    let skip = tape.skip_if_zero(&mut builder, current_value);
+
+                    let output =
+                        tape.target(&mut builder, offset + output_offset, current_value, span);
+                    let output_current_value = output.load(&mut builder, cell_t);
+                    let (new_value, _overflow) = builder
+                        .ins()
+                        .uadd_overflow(output_current_value, current_value);
+
+                    output.store(&mut builder, new_value);
+                    let place = tape.settle(&mut builder, place);
+                    let zero = builder.ins().iconst(cell_t, 0);
+                    place.store(&mut builder, zero);
+                    skipped(&mut builder, skip);
+                }
+                MultiplyAdd { offset, targets } => {
+                    if let Some(checked) = &checked {
+                        checked.multiply(&mut builder, &tape, offset, &targets, span);
+                        continue;
+                    }
+
+                    let place = tape.place(&mut builder, offset, span);
+                    let current_value = place.load(&mut builder, cell_t);
+                    let skip = tape.skip_if_zero(&mut builder, current_value);
+
+                    for (output_offset, coefficient) in targets {
+                        let mul_result = builder
+                            .ins()
+                            .imul_imm(current_value, width.wrap(coefficient) as i64);
+
+                        let output =
+                            tape.target(&mut builder, offset + output_offset, current_value, span);
+                        let output_current_value = output.load(&mut builder, cell_t);
+                        let new_value = builder.ins().iadd(output_current_value, mul_result);
+
+                        output.store(&mut builder, new_value);
+                    }
+                    let place = tape.settle(&mut builder, place);
+                    let zero = builder.ins().iconst(cell_t, 0);
+                    place.store(&mut builder, zero);
+                    skipped(&mut builder, skip);
+                }
+                Print { bytes } => {
+                    let len = builder.ins().iconst(size_t, bytes.len() as i64);
+                    let data_id = self.data(builder.func, bytes)?;
+
+                    let data = builder.ins().symbol_value(size_t, data_id);
+                    host.print(&mut builder, data, len, span);
+                }
+                Preload { cells } => {
+                    // the parser turns away longer tape images, but only the tape's worth fits
+                    let bytes = cells
+                        .iter()
+                        .take(self.config.tape_size)
+                        .flat_map(|cell| cell.to_le_bytes()[..width.bytes()].to_vec())
+                        .collect::<Vec<u8>>();
+                    let len = builder.ins().iconst(size_t, bytes.len() as i64);
+                    let data_id = self.data(builder.func, bytes)?;
+
+                    let data = builder.ins().symbol_value(size_t, data_id);
+                    let origin = builder.ins().iconst(size_t, 0);
+                    let tape_start = tape.addr(&mut builder, origin);
+                    builder.call_memcpy(self.module.target_config(), tape_start, data, len);
+                }
+                Scan { stride, target } => {
+                    let scan_block = builder.create_block();
+                    let step_block = builder.create_block();
+                    let after_block = builder.create_block();
+
+                    // on a wrapping tape the scan counts down the cells it has left to look at --
+                    // once it's looked at all of them without finding `target`, it never will
+                    let mut start_args = Vec::new();
+                    if let Guard::Wrap = tape.guard {
+                        builder.append_block_param(scan_block, size_t);
+                        start_args.push(builder.ins().iconst(size_t, config.tape_size as i64));
+                    }
+                    builder.ins().jump(scan_block, &start_args);
+                    builder.switch_to_block(scan_block);
+                    let current_value =
+                        tape.place(&mut builder, 0, span).load(&mut builder, cell_t);
+                    let found = builder
+                        .ins()
+                        .icmp_imm(IntCC::Equal, current_value, target as i64);
+                    builder.ins().brif(found, after_block, &[], step_block, &[]);
+
+                    builder.switch_to_block(step_block);
+                    builder.seal_block(step_block);
+                    let mut back_args = Vec::new();
+                    match tape.guard {
+                        Guard::Wrap => {
+                            let left = builder.block_params(scan_block)[0];
+                            let left = builder.ins().iadd_imm(left, -1);
+                            let done = builder.ins().icmp_imm(IntCC::Equal, left, 0);
+                            let ptr = builder.use_var(tape_ptr);
+                            host.stop_if(&mut builder, done, Stop::InfiniteLoop, span, ptr);
+                            tape.shift(&mut builder, stride, span);
+                            back_args.push(left);
+                        }
+                        // every cell past the edge is zero, so a scan for anything else leaving the
+                        // tape never ends
+                        Guard::Grow { state, .. } if target != 0 => {
+                            let ptr = builder.use_var(tape_ptr);
+                            let idx = builder.ins().iadd_imm(ptr, stride as i64);
+                            let off_tape = tape.off_growing_tape(&mut builder, state, idx);
+                            host.stop_if(&mut builder, off_tape, Stop::InfiniteLoop, span, ptr);
+                            builder.def_var(tape_ptr, idx);
+                        }
+                        _ => tape.shift(&mut builder, stride, span),
+                    }
+                    if let Some(tank) = &tank {
+                        tank.burn(&mut builder, span);
+                    }
+                    builder.ins().jump(scan_block, &back_args);
+
+                    builder.switch_to_block(after_block);
+                    builder.seal_block(scan_block);
+                    builder.seal_block(after_block);
+                }
+                DebugDump => {
+                    let Some(dprintf) = dprintf else {
+                        unreachable!()
+                    };
+                    let cells = DUMP_CELLS.min(config.tape_size);
+                    let signed = config.overflow == Overflow::Signed;
+                    let cell_format = if signed { " %ld" } else { " %lu" };
+                    let format = format!(
+                        "# {span} -- pointer on cell %ld:{}\n\0",
+                        cell_format.repeat(cells)
+                    );
+                    let data_id = self.data(builder.func, format.into_bytes())?;
+
+                    // stdout is buffered but stderr isn't, so what's been printed goes first
+                    host.flush(&mut builder, span);
+
+                    let mut args = vec![
+                        builder.ins().iconst(types::I32, 2),
+                        builder.ins().symbol_value(size_t, data_id),
+                        tape.position(&mut builder),
+                    ];
+                    for idx in 0..DUMP_CELLS {
+                        // `dprintf` ignores the arguments past the last cell
+                        if idx >= cells {
+                            args.push(builder.ins().iconst(types::I64, 0));
+                            continue;
+                        }
+
+                        let idx = builder.ins().iconst(size_t, idx as i64);
+                        let addr = tape.addr(&mut builder, idx);
+                        let value = builder.ins().load(cell_t, MemFlags::new(), addr, 0);
+                        args.push(match width {
+                            CellWidth::W64 => value,
+                            _ if signed => builder.ins().sextend(types::I64, value),
+                            _ => builder.ins().uextend(types::I64, value),
+                        });
+                    }
+                    builder.ins().call(dprintf, &args);
+                }
+            }
+        }
+
+        if !main {
+            let mut results = vec![builder.use_var(tape_ptr)];
+            if let Some(tank) = &tank {
+                results.push(builder.use_var(tank.steps));
+            }
+            builder.ins().return_(&results);
+
+            // the steps left don't matter anymore once the program has stopped
+            if self.jit {
+                builder.switch_to_block(leave_block);
+                builder.seal_block(leave_block);
+                let mut results = vec![builder.ins().iconst(size_t, STOPPED)];
+                if tank.is_some() {
+                    results.push(builder.ins().iconst(types::I64, 0));
+                }
+                builder.ins().return_(&results);
+            }
+            builder.finalize();
+
+            let name = format!("loop{}", self.loop_count);
+            self.loop_count += 1;
+            let id = self
+                .module
+                .declare_function(&name, Linkage::Local, &ctx.func.signature)?;
+            self.module.define_function(id, &mut ctx)?;
+            self.module.clear_context(&mut ctx);
+
+            return Ok(id);
+        }
+
+        builder.ins().jump(leave_block, &[]);
+        builder.switch_to_block(leave_block);
+        builder.seal_block(leave_block);
+        if let Guard::Grow { state, .. } = tape.guard {
+            let module = &mut *self.module;
+            dlsym! { module, builder.func;
+                fn free(size_t);
+            }
+            let base = builder.ins().load(size_t, MemFlags::trusted(), state, 0);
+            let low = builder.ins().load(size_t, MemFlags::trusted(), state, 8);
+            let start = tape.scale(&mut builder, low);
+            let start = builder.ins().iadd(base, start);
+            builder.ins().call(free, &[start]);
+        }
+        match self.jit {
+            true => builder.ins().return_(&[]),
+            // returning from `main` flushes stdout and exits successfully
+            false => {
+                let success = builder.ins().iconst(types::I32, 0);
+                builder.ins().return_(&[success])
+            }
+        };
+        builder.finalize();
+
+        let id = self
+            .module
+            .declare_function("main", Linkage::Export, &ctx.func.signature)?;
+        self.module.define_function(id, &mut ctx)?;
+        self.module.clear_context(&mut ctx);
+
+        Ok(id)
+    }
+}
@@ -0,0 +1,520 @@
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use anyhow::{Context, bail};
+use indoc::indoc;
+
+use boyfriend::config::{CellWidth, Config};
+use boyfriend::interpret::{Cell, Machine, Reader, print_dump};
+use boyfriend::ir::IR::{self, *};
+use boyfriend::ir::{Location, Node, Span, excerpt, match_brackets, unzip};
+
+const HELP: &str = indoc! {"
+    step [N]                  (s)  run N instructions, 1 by default
+    next                      (n)  like `step`, but a loop runs all at once
+    finish                    (f)  run until the loop the program is in ends
+    continue                  (c)  run until a breakpoint or the end of the program
+    break LINE[:COLUMN]       (b)  stop before an instruction from that place in the source
+    break when cell[N] OP X        stop once cell N (counting from where the program started)
+                                   compares to X -- OP is one of == != < <= > >=
+    break when ptr OP X            stop once the pointer compares to X
+    delete N                  (d)  remove breakpoint N
+    breakpoints                    list the breakpoints
+    tape [RADIUS]             (t)  show the cells around the pointer, 8 either side by default
+    where                     (w)  show the next instruction and where it is in the source
+    help                      (h)  show this
+    quit                      (q)  leave the debugger
+    an empty line repeats the last command
+"};
+
+/// the lines of source shown either side of the next instruction
+const CONTEXT: usize = 2;
+
+/// the cells shown either side of the pointer by `tape`
+const RADIUS: usize = 8;
+
+/// how a condition breakpoint compares its subject to its value
+type Compare = fn(&i128, &i128) -> bool;
+
+/// what a condition breakpoint looks at
+#[derive(Clone, Copy)]
+enum Subject {
+    /// a cell, counting from the one the program started on
+    Cell(isize),
+    /// the pointer, counting from the cell the program started on
+    Pointer,
+}
+
+enum Breakpoint {
+    /// stop before running an instruction from this place in the source -- a whole line if
+    /// there's no column
+    Source { line: usize, column: Option<usize> },
+    /// stop once `subject` compares to `value` -- only when it starts to, so it doesn't stop
+    /// after every instruction while it stays that way
+    When {
+        subject: Subject,
+        op: &'static str,
+        compare: Compare,
+        value: i128,
+        held: bool,
+    },
+}
+
+impl std::fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Breakpoint::Source { line, column: None } => write!(f, "line {line}"),
+            Breakpoint::Source {
+                line,
+                column: Some(column),
+            } => write!(f, "{line}:{column}"),
+            Breakpoint::When {
+                subject, op, value, ..
+            } => match subject {
+                Subject::Cell(cell) => write!(f, "when cell[{cell}] {op} {value}"),
+                Subject::Pointer => write!(f, "when ptr {op} {value}"),
+            },
+        }
+    }
+}
+
+/// parse the `LINE[:COLUMN]` or `when ...` after `break`
+fn parse_breakpoint(text: &str) -> anyhow::Result<Breakpoint> {
+    let Some(condition) = text.strip_prefix("when") else {
+        let (line, column) = match text.split_once(':') {
+            Some((line, column)) => (line, Some(column.parse().context("bad column")?)),
+            None => (text, None),
+        };
+        let line = line.parse().context("bad line")?;
+        if line == 0 || column == Some(0) {
+            bail!("lines and columns start at 1");
+        }
+
+        return Ok(Breakpoint::Source { line, column });
+    };
+
+    // the two-character operators go first so `<=` isn't read as `<`
+    let operators: [(&'static str, Compare); 6] = [
+        ("==", i128::eq),
+        ("!=", i128::ne),
+        ("<=", i128::le),
+        (">=", i128::ge),
+        ("<", i128::lt),
+        (">", i128::gt),
+    ];
+    let Some((lhs, op, compare, rhs)) = operators.iter().find_map(|&(op, compare)| {
+        condition
+            .split_once(op)
+            .map(|(lhs, rhs)| (lhs.trim(), op, compare, rhs.trim()))
+    }) else {
+        bail!("expected a comparison, eg. `break when cell[42] == 10`");
+    };
+
+    let subject = match lhs
+        .strip_prefix("cell[")
+        .and_then(|lhs| lhs.strip_suffix(']'))
+    {
+        Some(cell) => Subject::Cell(cell.trim().parse().context("bad cell number")?),
+        None if lhs == "ptr" => Subject::Pointer,
+        None => bail!("expected `cell[N]` or `ptr`, found `{lhs}`"),
+    };
+    let value = rhs.parse().context("bad value")?;
+
+    Ok(Breakpoint::When {
+        subject,
+        op,
+        compare,
+        value,
+        held: false,
+    })
+}
+
+/// a program paused in the interpreter
+struct Debugger<'a, C: Cell> {
+    path: &'a Path,
+    source: &'a str,
+    insts: Vec<IR>,
+    spans: Vec<Span>,
+    machine: Machine<C>,
+    config: Config,
+    input: Reader<'a>,
+    /// numbered from 1, `None` once deleted so the numbers stay the same
+    breakpoints: Vec<Option<Breakpoint>>,
+    /// instructions run so far
+    steps: u64,
+    /// why the program can't run any more
+    finished: Option<String>,
+    /// whether the program's output ended with a newline (or there isn't any), so the
+    /// debugger's own output starts on a fresh line
+    at_line_start: bool,
+}
+
+impl<C: Cell> Debugger<'_, C> {
+    /// the number held by a cell, counting from the one the program started on -- `None` if it's
+    /// off the tape
+    fn cell(&self, cell: isize) -> Option<i128> {
+        let idx = self.machine.origin.checked_add_signed(cell)?;
+        let bits = self.machine.memory.get(idx)?.to_u64();
+
+        Some(self.config.cell_value(bits))
+    }
+
+    fn holds(&self, subject: Subject, compare: Compare, value: i128) -> bool {
+        let current = match subject {
+            Subject::Cell(cell) => self.cell(cell),
+            Subject::Pointer => {
+                Some(self.machine.ptr as isize as i128 - self.machine.origin as i128)
+            }
+        };
+
+        current.is_some_and(|current| compare(&current, &value))
+    }
+
+    /// run instructions until `done` says to stop (given the number run so far), a breakpoint
+    /// is hit or the program ends
+    fn resume(&mut self, done: impl Fn(&Machine<C>, u64) -> bool) -> anyhow::Result<()> {
+        if let Some(reason) = &self.finished {
+            bail!("there's nothing left to run -- {reason}");
+        }
+
+        let mut count = 0;
+        let stopped = loop {
+            let input = &mut self.input;
+            let at_line_start = &mut self.at_line_start;
+            let spans = &self.spans;
+            let result = self.machine.step(
+                &self.insts,
+                &mut || Ok(input.read()?),
+                &mut |bytes| {
+                    let mut stdout = std::io::stdout().lock();
+                    stdout.write_all(bytes)?;
+                    stdout.flush()?;
+                    if let Some(&last) = bytes.last() {
+                        *at_line_start = last == b'\n';
+                    }
+                    Ok(())
+                },
+                &mut |machine| {
+                    let pointer = machine.ptr as isize - machine.origin as isize;
+                    print_dump(spans[machine.ip], pointer, machine.dump());
+                    Ok(())
+                },
+            );
+            if let Err(err) = result {
+                break Some(format!("the program stopped with an error: {err}"));
+            }
+            self.steps += 1;
+            count += 1;
+
+            if self.machine.ip >= self.insts.len() {
+                break Some(format!("the program ended after {} steps", self.steps));
+            }
+
+            if let Some(number) = self.hit() {
+                self.fresh_line();
+                eprintln!(
+                    "* stopped at breakpoint {number}: {}",
+                    self.breakpoint(number)
+                );
+                break None;
+            }
+            if done(&self.machine, count) {
+                break None;
+            }
+        };
+
+        self.fresh_line();
+        match stopped {
+            Some(reason) => {
+                eprintln!("* {reason}");
+                // an error still has an instruction to point at
+                if self.machine.ip < self.insts.len() {
+                    self.show_where();
+                }
+                self.finished = Some(reason);
+            }
+            None => self.show_where(),
+        }
+
+        Ok(())
+    }
+
+    /// the number of the first breakpoint to stop the program before its next instruction --
+    /// every condition is updated, even after one is hit
+    fn hit(&mut self) -> Option<usize> {
+        let span = self.spans[self.machine.ip];
+        let mut hit = None;
+
+        for number in 1..=self.breakpoints.len() {
+            let stop = match self.breakpoints[number - 1] {
+                Some(Breakpoint::Source { line, column }) => covers(span, line, column),
+                Some(Breakpoint::When {
+                    subject,
+                    compare,
+                    value,
+                    held,
+                    ..
+                }) => {
+                    let holds = self.holds(subject, compare, value);
+                    if let Some(Breakpoint::When { held, .. }) = &mut self.breakpoints[number - 1] {
+                        *held = holds;
+                    }
+                    holds && !held
+                }
+                None => false,
+            };
+            if stop && hit.is_none() {
+                hit = Some(number);
+            }
+        }
+
+        hit
+    }
+
+    fn breakpoint(&self, number: usize) -> &Breakpoint {
+        self.breakpoints[number - 1]
+            .as_ref()
+            .expect("deleted breakpoints aren't hit")
+    }
+
+    fn fresh_line(&mut self) {
+        if !self.at_line_start {
+            eprintln!();
+            self.at_line_start = true;
+        }
+    }
+
+    fn show_where(&self) {
+        let ip = self.machine.ip;
+        eprintln!(
+            "* step {}, instruction {ip}: `{}`",
+            self.steps, self.insts[ip]
+        );
+        eprint!(
+            "{}",
+            excerpt(self.path, self.source, self.spans[ip], CONTEXT)
+        );
+    }
+
+    fn show_tape(&self, radius: usize) {
+        let Machine {
+            ref memory,
+            ptr,
+            origin,
+            ..
+        } = self.machine;
+        // the pointer can be off the tape with `--bounds abort`, below 0 as well
+        let ptr = ptr as isize;
+        let len = memory.len() as isize;
+        let start = ptr.saturating_sub_unsigned(radius).clamp(0, len) as usize;
+        let end = ptr.saturating_add_unsigned(radius).clamp(-1, len - 1) + 1;
+
+        let columns = (start..end as usize)
+            .map(|idx| {
+                let cell = (idx as isize - origin as isize).to_string();
+                let value = self.config.cell_value(memory[idx].to_u64()).to_string();
+                (cell, value)
+            })
+            .collect::<Vec<_>>();
+        let widths = columns
+            .iter()
+            .map(|(cell, value)| cell.len().max(value.len()))
+            .collect::<Vec<_>>();
+
+        let mut cells = String::from(" cell |");
+        let mut values = String::from("value |");
+        let mut marker = String::from("      |");
+        for (idx, ((cell, value), &width)) in (start..).zip(columns.iter().zip(&widths)) {
+            cells += &format!(" {cell:>width$}");
+            values += &format!(" {value:>width$}");
+            let caret = if idx as isize == ptr { "^" } else { "" };
+            marker += &format!(" {caret:>width$}");
+        }
+
+        eprintln!("* the pointer is on cell {}", ptr - origin as isize);
+        eprintln!("{cells}\n{values}\n{}", marker.trim_end());
+    }
+
+    /// run one command -- `false` means quit
+    fn command(&mut self, line: &str) -> anyhow::Result<bool> {
+        let (name, argument) = line
+            .split_once(char::is_whitespace)
+            .map_or((line, ""), |(name, argument)| (name, argument.trim()));
+
+        match name {
+            "step" | "s" => {
+                let count = match argument {
+                    "" => 1,
+                    count => count.parse().context("bad number of steps")?,
+                };
+                self.resume(|_, taken| taken >= count)?;
+            }
+            "next" | "n" => match self.insts.get(self.machine.ip) {
+                Some(&LoopStart { end_index }) => {
+                    self.resume(|machine, _| machine.ip == end_index + 1)?
+                }
+                _ => self.resume(|_, _| true)?,
+            },
+            "finish" | "f" => {
+                let ip = self.machine.ip;
+                // the innermost loop around the next instruction
+                let end = self.insts[..ip.min(self.insts.len())]
+                    .iter()
+                    .rev()
+                    .find_map(|inst| match *inst {
+                        LoopStart { end_index } if end_index >= ip => Some(end_index),
+                        _ => None,
+                    });
+                match end {
+                    Some(end_index) => self.resume(|machine, _| machine.ip == end_index + 1)?,
+                    None if self.finished.is_none() => bail!("the program isn't in a loop"),
+                    None => self.resume(|_, _| true)?,
+                }
+            }
+            "continue" | "c" => self.resume(|_, _| false)?,
+            "break" | "b" => {
+                let mut breakpoint = parse_breakpoint(argument)?;
+                match breakpoint {
+                    Breakpoint::Source { line, column } => {
+                        if !self.spans.iter().any(|&span| covers(span, line, column)) {
+                            eprintln!(
+                                "* warning: no instruction comes from there (it may have been \
+                                 optimized away -- try `-O0`)"
+                            );
+                        }
+                    }
+                    Breakpoint::When {
+                        subject,
+                        compare,
+                        value,
+                        ref mut held,
+                        ..
+                    } => *held = self.holds(subject, compare, value),
+                }
+                self.breakpoints.push(Some(breakpoint));
+                eprintln!(
+                    "* breakpoint {}: {}",
+                    self.breakpoints.len(),
+                    self.breakpoint(self.breakpoints.len())
+                );
+            }
+            "delete" | "d" => {
+                let number = argument.parse::<usize>().context("bad breakpoint number")?;
+                match number
+                    .checked_sub(1)
+                    .and_then(|idx| self.breakpoints.get_mut(idx))
+                {
+                    Some(breakpoint @ Some(_)) => *breakpoint = None,
+                    _ => bail!("there's no breakpoint {number}"),
+                }
+            }
+            "breakpoints" => {
+                for (number, breakpoint) in (1..).zip(&self.breakpoints) {
+                    if let Some(breakpoint) = breakpoint {
+                        eprintln!("{number}: {breakpoint}");
+                    }
+                }
+            }
+            "tape" | "t" => {
+                let radius = match argument {
+                    "" => RADIUS,
+                    radius => radius.parse().context("bad radius")?,
+                };
+                self.show_tape(radius);
+            }
+            "where" | "w" => match &self.finished {
+                Some(reason) if self.machine.ip >= self.insts.len() => eprintln!("* {reason}"),
+                _ => self.show_where(),
+            },
+            "help" | "h" => eprint!("{HELP}"),
+            "quit" | "q" => return Ok(false),
+            _ => bail!("unknown command `{name}` -- try `help`"),
+        }
+
+        Ok(true)
+    }
+}
+
+/// whether `span` comes from `line` (and `column`, if there is one)
+fn covers(span: Span, line: usize, column: Option<usize>) -> bool {
+    match column {
+        Some(column) => (span.start..=span.end).contains(&Location { line, column }),
+        None => (span.start.line..=span.end.line).contains(&line),
+    }
+}
+
+/// step through a program in the interpreter, taking commands from stdin. `source` is what
+/// `ir` was compiled from, and `input` is fed to `,` instead of stdin if there is one.
+pub fn debug(
+    path: &Path,
+    source: &str,
+    ir: Vec<Node>,
+    config: Config,
+    input: Option<Vec<u8>>,
+) -> anyhow::Result<()> {
+    match config.cell_width {
+        CellWidth::W8 => run::<u8>(path, source, ir, config, input),
+        CellWidth::W16 => run::<u16>(path, source, ir, config, input),
+        CellWidth::W32 => run::<u32>(path, source, ir, config, input),
+        CellWidth::W64 => run::<u64>(path, source, ir, config, input),
+    }
+}
+
+fn run<C: Cell>(
+    path: &Path,
+    source: &str,
+    mut ir: Vec<Node>,
+    config: Config,
+    input: Option<Vec<u8>>,
+) -> anyhow::Result<()> {
+    eprintln!("* matching brackets");
+    match_brackets(&mut ir);
+    let (insts, spans) = unzip(&ir);
+    if insts.is_empty() {
+        bail!("there's nothing to debug -- the program is empty");
+    }
+
+    let mut stdin = std::io::stdin();
+    let mut debugger = Debugger {
+        path,
+        source,
+        insts,
+        spans,
+        machine: Machine::<C>::new(&config),
+        config,
+        input: Reader::new(&mut stdin, input),
+        breakpoints: Vec::new(),
+        steps: 0,
+        finished: None,
+        at_line_start: true,
+    };
+
+    eprintln!("* type `help` for a list of commands");
+    debugger.show_where();
+
+    let mut last = String::new();
+    loop {
+        eprint!("(boyfriend) ");
+        std::io::stderr().flush()?;
+
+        let mut line = String::new();
+        if std::io::stdin().lock().read_line(&mut line)? == 0 {
+            eprintln!();
+            return Ok(());
+        }
+        let line = match line.trim() {
+            "" => last.clone(),
+            line => line.to_string(),
+        };
+        if line.is_empty() {
+            continue;
+        }
+
+        match debugger.command(&line) {
+            Ok(true) => {}
+            Ok(false) => return Ok(()),
+            Err(err) => eprintln!("! {err:#}"),
+        }
+        last = line;
+    }
+}
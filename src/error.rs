@@ -0,0 +1,116 @@
+use crate::fuel::Exhausted;
+use crate::ir::Span;
+
+/// what stopped a running program in the middle of an instruction
+#[derive(Debug)]
+pub enum Fault {
+    /// a cell off the tape was touched with `Bounds::Abort` -- `cell` counts from the cell the
+    /// program started on
+    OutOfBounds { cell: isize, tape_size: usize },
+    /// a cell went past what it can hold with `Overflow::Unsigned` or `Overflow::Signed`
+    Overflow {
+        cell: isize,
+        value: i128,
+        min: i128,
+        max: i128,
+    },
+    /// `,` with `Eof::Error`, once the input had run out
+    InputRanOut,
+    /// a scan that would never find what it's looking for
+    InfiniteLoop,
+    /// there wasn't enough memory to grow the tape to `cell` with `Bounds::Grow`
+    OutOfMemory { cell: isize },
+    /// reading the input or writing the output failed
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fault::OutOfBounds { cell, tape_size } => write!(
+                f,
+                "the pointer left the tape -- cell {cell} is out of bounds (the tape has \
+                 {tape_size} cells)"
+            ),
+            Fault::Overflow {
+                cell,
+                value,
+                min,
+                max,
+            } => write!(
+                f,
+                "cell {cell} overflowed -- {value} doesn't fit in {min}..={max}"
+            ),
+            Fault::InputRanOut => write!(f, "the input ran out"),
+            Fault::InfiniteLoop => write!(f, "infinite loop detected"),
+            Fault::OutOfMemory { cell } => {
+                write!(f, "out of memory growing the tape to cell {cell}")
+            }
+            Fault::Io(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for Fault {
+    fn from(err: std::io::Error) -> Self {
+        Fault::Io(err)
+    }
+}
+
+/// everything that can go wrong between reading a program and running it to the end
+#[derive(Debug)]
+pub enum Error {
+    /// the brackets in the source don't match up -- where every unmatched one is
+    Brackets {
+        opening: Vec<Span>,
+        closing: Vec<Span>,
+    },
+    /// a line of textual IR couldn't be read
+    Parse { line: usize, message: String },
+    /// `PassManager` was asked for a pass that doesn't exist
+    UnknownPass(String),
+    /// the program stopped because of `fault` in the instruction at `span`
+    Fault { fault: Fault, span: Span },
+    /// the program ran out of steps or time
+    Exhausted(Exhausted),
+    /// writing the output failed after the program finished
+    Io(std::io::Error),
+    /// cranelift couldn't compile the program
+    Codegen(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Brackets { opening, closing } => match (opening.len(), closing.len()) {
+                (opening, 0) => write!(f, "{opening} unmatched opening bracket(s) (`[`)"),
+                (0, closing) => write!(f, "{closing} unmatched closing bracket(s) (`]`)"),
+                (opening, closing) => write!(
+                    f,
+                    "{opening} unmatched opening bracket(s) (`[`) and {closing} unmatched \
+                     closing bracket(s) (`]`)"
+                ),
+            },
+            Error::Parse { line, message } => write!(f, "line {line}: {message}"),
+            Error::UnknownPass(name) => write!(f, "no pass named `{name}`"),
+            Error::Fault { fault, span } => write!(f, "in the instruction at {span}: {fault}"),
+            Error::Exhausted(exhausted) => write!(f, "{exhausted}"),
+            Error::Io(err) => write!(f, "{err}"),
+            Error::Codegen(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<Exhausted> for Error {
+    fn from(exhausted: Exhausted) -> Self {
+        Error::Exhausted(exhausted)
+    }
+}
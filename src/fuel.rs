@@ -0,0 +1,128 @@
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::ir::IR::{self, *};
+use crate::ir::Span;
+
+/// the exit code of a program stopped by `--max-steps` or `--timeout` -- the same as `timeout(1)`
+pub const EXIT_CODE: i32 = 124;
+
+/// the number of steps handed out at once -- the clock is checked every time they run out
+const TANK: u64 = 1 << 16;
+
+/// which limit stopped the program
+#[derive(Debug, Clone, Copy)]
+pub enum Limit {
+    Steps(u64),
+    Time(Duration),
+}
+
+impl std::fmt::Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Limit::Steps(steps) => write!(f, "ran out of steps (`--max-steps {steps}`)"),
+            Limit::Time(timeout) => write!(f, "ran out of time (`--timeout {timeout:?}`)"),
+        }
+    }
+}
+
+/// a program stopped early by `--max-steps` or `--timeout`
+#[derive(Debug)]
+pub struct Exhausted {
+    pub limit: Limit,
+    /// the loop the program was in, or the instruction it was on when it wasn't in one
+    pub span: Span,
+    pub in_loop: bool,
+}
+
+impl std::fmt::Display for Exhausted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.in_loop {
+            true => write!(f, "{} in the loop at {}", self.limit, self.span),
+            false => write!(f, "{} at {}", self.limit, self.span),
+        }
+    }
+}
+
+impl std::error::Error for Exhausted {}
+
+/// how much longer a program may run. steps are handed out a tank at a time, so the hot path
+/// only counts down
+pub struct Fuel {
+    tank: u64,
+    max_steps: Option<u64>,
+    /// the steps handed out so far
+    used: u64,
+    timeout: Option<Duration>,
+    start: Instant,
+}
+
+impl Fuel {
+    /// `None` when the program can run for as long as it likes
+    pub fn new(config: &Config) -> Option<Self> {
+        if config.max_steps.is_none() && config.timeout.is_none() {
+            return None;
+        }
+
+        Some(Self {
+            tank: 0,
+            max_steps: config.max_steps,
+            used: 0,
+            timeout: config.timeout,
+            start: Instant::now(),
+        })
+    }
+
+    /// take a step
+    #[inline]
+    pub fn burn(&mut self) -> Result<(), Limit> {
+        if self.tank == 0 {
+            self.tank = self.refuel()?;
+        }
+        self.tank -= 1;
+
+        Ok(())
+    }
+
+    /// the next tank of steps, unless the program is out of steps or time
+    pub fn refuel(&mut self) -> Result<u64, Limit> {
+        if let Some(timeout) = self.timeout
+            && self.start.elapsed() >= timeout
+        {
+            return Err(Limit::Time(timeout));
+        }
+
+        let tank = match self.max_steps {
+            Some(max_steps) if self.used == max_steps => return Err(Limit::Steps(max_steps)),
+            Some(max_steps) => TANK.min(max_steps - self.used),
+            None => TANK,
+        };
+        self.used += tank;
+
+        Ok(tank)
+    }
+}
+
+/// the innermost loop around the instruction at `idx`, in instructions with their brackets
+/// matched -- the `]` of a loop counts as inside it
+pub fn innermost_loop(insts: &[IR], idx: usize) -> Option<usize> {
+    insts[..idx]
+        .iter()
+        .rposition(|inst| matches!(*inst, LoopStart { end_index } if end_index >= idx))
+}
+
+/// what stopped the program on the instruction at `idx`
+pub fn exhausted(insts: &[IR], spans: &[Span], idx: usize, limit: Limit) -> Exhausted {
+    match innermost_loop(insts, idx) {
+        Some(start) => Exhausted {
+            limit,
+            span: spans[start],
+            in_loop: true,
+        },
+        None => Exhausted {
+            limit,
+            span: spans[idx],
+            in_loop: false,
+        },
+    }
+}
@@ -1,100 +1,527 @@
-use anyhow::{Context, bail};
 use memchr::{memchr, memrchr};
+use std::cell::RefCell;
 use std::io::{Read, Write};
 
-use crate::chunk_list::ChunkList;
+use crate::config::{Bounds, CellWidth, Config, Eof, Overflow};
+use crate::error::{Error, Fault};
+use crate::fuel::{self, Fuel};
 use crate::ir::IR::{self, *};
+use crate::ir::{DUMP_CELLS, Node, Span, unzip};
+use crate::profile;
 
-pub fn interpret(ir: ChunkList<IR>) -> anyhow::Result<()> {
-    let insts = ir.into_iter().collect::<Vec<IR>>();
-    let mut memory = vec![0u8; 0x10000];
-    let mut ptr: usize = 0;
-    let mut ip = 0;
+/// a cell on the tape -- the type picked by `CellWidth`
+pub trait Cell: Copy + PartialEq + Default {
+    /// truncate `value` to the cell's width
+    fn from_u64(value: u64) -> Self;
+    fn to_u64(self) -> u64;
+    fn wrapping_add(self, other: Self) -> Self;
+    fn wrapping_mul(self, other: Self) -> Self;
 
-    while ip < insts.len() {
-        let inst = insts[ip];
+    /// the index of the first `needle` in `haystack`
+    fn find(haystack: &[Self], needle: Self) -> Option<usize> {
+        haystack.iter().position(|&cell| cell == needle)
+    }
+
+    /// the index of the last `needle` in `haystack`
+    fn rfind(haystack: &[Self], needle: Self) -> Option<usize> {
+        haystack.iter().rposition(|&cell| cell == needle)
+    }
+}
+
+macro_rules! impl_cell {
+    ($($ty:ty { $($extra:item)* })+) => {
+        $(impl Cell for $ty {
+            fn from_u64(value: u64) -> Self {
+                value as $ty
+            }
+
+            fn to_u64(self) -> u64 {
+                self as u64
+            }
+
+            fn wrapping_add(self, other: Self) -> Self {
+                <$ty>::wrapping_add(self, other)
+            }
+
+            fn wrapping_mul(self, other: Self) -> Self {
+                <$ty>::wrapping_mul(self, other)
+            }
+
+            $($extra)*
+        })+
+    };
+}
+
+impl_cell! {
+    u8 {
+        fn find(haystack: &[Self], needle: Self) -> Option<usize> {
+            memchr(needle, haystack)
+        }
+
+        fn rfind(haystack: &[Self], needle: Self) -> Option<usize> {
+            memrchr(needle, haystack)
+        }
+    }
+    u16 {}
+    u32 {}
+    u64 {}
+}
+
+/// find the first cell equal to `target` when stepping from `ptr` by `stride`, wrapping around
+/// the tape. `None` means the scan never ends.
+pub fn scan<C: Cell>(memory: &[C], ptr: usize, stride: isize, target: C) -> Option<usize> {
+    match stride {
+        1 => C::find(&memory[ptr..], target)
+            .map(|offset| offset + ptr)
+            .or_else(|| C::find(&memory[..ptr], target)),
+        -1 => C::rfind(&memory[..=ptr], target)
+            .or_else(|| C::rfind(&memory[ptr..], target).map(|offset| offset + ptr)),
+        _ => {
+            let len = memory.len() as isize;
+            let mut idx = ptr;
+            for _ in 0..memory.len() {
+                if memory[idx] == target {
+                    return Some(idx);
+                }
+                idx = (idx as isize + stride).rem_euclid(len) as usize;
+            }
+
+            None
+        }
+    }
+}
+
+/// like `scan`, but stopping at the edge of the tape -- `Err` has the last cell looked at
+fn scan_to_edge<C: Cell>(
+    memory: &[C],
+    ptr: usize,
+    stride: isize,
+    target: C,
+) -> Result<usize, usize> {
+    match stride {
+        1 => C::find(&memory[ptr..], target)
+            .map(|offset| offset + ptr)
+            .ok_or(memory.len() - 1),
+        -1 => C::rfind(&memory[..=ptr], target).ok_or(0),
+        _ => {
+            let mut idx = ptr;
+            loop {
+                if memory[idx] == target {
+                    return Ok(idx);
+                }
+                match idx.checked_add_signed(stride) {
+                    Some(next) if next < memory.len() => idx = next,
+                    _ => return Err(idx),
+                }
+            }
+        }
+    }
+}
+
+/// the state of a running program
+pub struct Machine<C: Cell> {
+    pub memory: Vec<C>,
+    /// the index of the current cell in `memory` -- with `Bounds::Abort` it can be off the tape
+    /// (wrapping around below 0), until a cell there is touched
+    pub ptr: usize,
+    pub ip: usize,
+    /// the index of the cell the program started on -- it moves when the tape grows to the left
+    pub origin: usize,
+    config: Config,
+}
+
+impl<C: Cell> Machine<C> {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            memory: vec![C::default(); config.tape_size],
+            ptr: 0,
+            ip: 0,
+            origin: 0,
+            config: *config,
+        }
+    }
+
+    /// the index in `memory` of the cell `offset` cells away from the pointer, applying the
+    /// bounds policy if it's off the tape
+    fn index(&mut self, offset: isize) -> Result<usize, Fault> {
+        let len = self.memory.len() as isize;
+        let idx = self.ptr as isize + offset;
+        if (0..len).contains(&idx) {
+            return Ok(idx as usize);
+        }
+
+        match self.config.bounds {
+            Bounds::Wrap => Ok(idx.rem_euclid(len) as usize),
+            Bounds::Abort => Err(Fault::OutOfBounds {
+                cell: idx - self.origin as isize,
+                tape_size: len as usize,
+            }),
+            Bounds::Grow => Ok(self.grow(idx)),
+        }
+    }
 
-        match inst {
+    /// make the tape big enough to hold `idx` (an index into `memory`, possibly negative),
+    /// returning where that cell ends up
+    fn grow(&mut self, idx: isize) -> usize {
+        let len = self.memory.len();
+        if idx < 0 {
+            let extra = len.max(idx.unsigned_abs());
+            self.memory
+                .splice(0..0, std::iter::repeat_n(C::default(), extra));
+            self.ptr += extra;
+            self.origin += extra;
+            (idx + extra as isize) as usize
+        } else {
+            self.memory
+                .resize((len * 2).max(idx as usize + 1), C::default());
+            idx as usize
+        }
+    }
+
+    /// add `amount` to the cell at `idx`, wrapping around or failing if it overflows
+    fn add(&mut self, idx: usize, amount: i128) -> Result<(), Fault> {
+        let cell = &mut self.memory[idx];
+        if self.config.overflow == Overflow::Wrap {
+            *cell = cell.wrapping_add(C::from_u64(amount as u64));
+            return Ok(());
+        }
+
+        let value = self.config.cell_value(cell.to_u64()) + amount;
+        match self.config.fit(value) {
+            Some(bits) => *cell = C::from_u64(bits),
+            None => return Err(self.overflowed(idx, value)),
+        }
+
+        Ok(())
+    }
+
+    /// the number in the cell at `idx`, which is about to be counted down to zero by a loop (or
+    /// an idiom standing in for one) -- that overflows if it's negative
+    fn countdown(&self, idx: usize) -> Result<i128, Fault> {
+        let value = self.config.cell_value(self.memory[idx].to_u64());
+        if value < 0 {
+            return Err(self.overflowed(idx, self.config.cell_range().0 - 1));
+        }
+
+        Ok(value)
+    }
+
+    /// the fault for the cell at `idx` overflowing to `value`
+    fn overflowed(&self, idx: usize, value: i128) -> Fault {
+        let (min, max) = self.config.cell_range();
+        Fault::Overflow {
+            cell: idx as isize - self.origin as isize,
+            value,
+            min,
+            max,
+        }
+    }
+
+    /// the numbers in the first `DUMP_CELLS` cells, for `DebugDump`
+    pub fn dump(&self) -> impl Iterator<Item = i128> {
+        self.memory[self.origin..]
+            .iter()
+            .take(DUMP_CELLS)
+            .map(|cell| self.config.cell_value(cell.to_u64()))
+    }
+
+    /// execute the instruction at `ip` -- `input` is called for every `,` (returning `None` once
+    /// the input has run out), `output` for every `.` (and constant output) and `dump` for every
+    /// `#`. `insts` must have its brackets matched.
+    pub fn step(
+        &mut self,
+        insts: &[IR],
+        input: &mut impl FnMut() -> Result<Option<u8>, Fault>,
+        output: &mut impl FnMut(&[u8]) -> Result<(), Fault>,
+        dump: &mut impl FnMut(&Self) -> Result<(), Fault>,
+    ) -> Result<(), Fault> {
+        match insts[self.ip] {
             Shift { amount } => {
-                ptr = ptr.overflowing_add_signed(amount).0 & 0xffff;
+                self.ptr = match self.config.bounds {
+                    // the pointer can be off the tape, as long as nothing off it is touched
+                    Bounds::Abort => self.ptr.wrapping_add_signed(amount),
+                    Bounds::Wrap | Bounds::Grow => self.index(amount)?,
+                };
             }
-            Arithmetic { amount } => {
-                memory[ptr] = memory[ptr].overflowing_add_signed(amount).0;
+            Arithmetic { amount, offset } => {
+                let idx = self.index(offset)?;
+                self.add(idx, amount as i128)?;
             }
             LoopStart { end_index } => {
-                if memory[ptr] == 0 {
-                    ip = end_index;
+                let idx = self.index(0)?;
+                if self.memory[idx] == C::default() {
+                    self.ip = end_index;
                 }
             }
             LoopEnd { start_index } => {
-                if memory[ptr] != 0 {
-                    ip = start_index;
+                let idx = self.index(0)?;
+                if self.memory[idx] != C::default() {
+                    self.ip = start_index;
                 }
             }
-            Input => {
-                memory[ptr] = std::io::stdin()
-                    .bytes()
-                    .next()
-                    .context("no stdin?")?
-                    .context("failed to read stdin")?;
+            Input { offset } => {
+                let idx = self.index(offset)?;
+                match (input()?, self.config.eof) {
+                    (Some(byte), _) => self.memory[idx] = C::from_u64(byte as u64),
+                    (None, Eof::Zero) => self.memory[idx] = C::default(),
+                    (None, Eof::MinusOne) => self.memory[idx] = C::from_u64(u64::MAX),
+                    (None, Eof::Unchanged) => {}
+                    (None, Eof::Error) => return Err(Fault::InputRanOut),
+                }
             }
-            Output => {
-                std::io::stdout().write_all(&[memory[ptr]])?;
-                std::io::stdout().flush()?;
+            Output { offset } => {
+                let idx = self.index(offset)?;
+                output(&[self.memory[idx].to_u64() as u8])?;
             }
 
             // idioms
-            Zero => {
-                memory[ptr] = 0;
+            Zero { offset } => {
+                let idx = self.index(offset)?;
+                self.countdown(idx)?;
+                self.memory[idx] = C::default();
+            }
+            Set { value, offset } => {
+                let idx = self.index(offset)?;
+                self.memory[idx] = C::from_u64(value);
             }
             Multiply {
                 amount,
+                offset,
                 output_offset,
             } => {
-                let new_ptr = ptr.overflowing_add_signed(output_offset).0 & 0xffff;
-                memory[new_ptr] += memory[ptr].overflowing_mul(amount as u8).0;
-                memory[ptr] = 0;
-            }
-            Move { output_offset } => {
-                let new_ptr = ptr.overflowing_add_signed(output_offset).0 & 0xffff;
-                memory[new_ptr] += memory[ptr];
-                memory[ptr] = 0;
-            }
-            AnchorRight => {
-                if memory[ptr] == 0 {
-                    ip += 1;
-                    continue;
+                // these stand in for loops, so the targets are only touched when the loop would
+                // have run
+                let idx = self.index(offset)?;
+                if self.memory[idx] != C::default() {
+                    // growing the tape moves cells around, so grow it before taking any indices
+                    self.index(offset + output_offset)?;
+                    let idx = self.index(offset)?;
+                    let new_idx = self.index(offset + output_offset)?;
+                    let count = self.countdown(idx)?;
+                    self.add(new_idx, count * amount as i128)?;
+                    self.memory[idx] = C::default();
                 }
-                memory[ptr] = memory[ptr].overflowing_sub(1).0;
-                if let Some(anchor) = memchr(255, &memory[ptr..])
-                    .map(|offset| offset + ptr)
-                    .or_else(|| memchr(255, &memory))
-                {
-                    ptr = anchor;
-                    memory[ptr] = 0;
-                } else {
-                    bail!("[boyfriend] infinite loop detected, halting");
+            }
+            Move {
+                offset,
+                output_offset,
+            } => {
+                let idx = self.index(offset)?;
+                if self.memory[idx] != C::default() {
+                    self.index(offset + output_offset)?;
+                    let idx = self.index(offset)?;
+                    let new_idx = self.index(offset + output_offset)?;
+                    let count = self.countdown(idx)?;
+                    self.add(new_idx, count)?;
+                    self.memory[idx] = C::default();
                 }
             }
-            AnchorLeft => {
-                if memory[ptr] == 0 {
-                    ip += 1;
-                    continue;
+            MultiplyAdd {
+                offset,
+                ref targets,
+            } => {
+                let idx = self.index(offset)?;
+                if self.memory[idx] != C::default() {
+                    for &(output_offset, _) in targets {
+                        self.index(offset + output_offset)?;
+                    }
+                    let idx = self.index(offset)?;
+                    let count = self.countdown(idx)?;
+                    for &(output_offset, coefficient) in targets {
+                        let new_idx = self.index(offset + output_offset)?;
+                        self.add(new_idx, count * coefficient as i128)?;
+                    }
+                    self.memory[idx] = C::default();
                 }
-                memory[ptr] = memory[ptr].overflowing_sub(1).0;
-                if let Some(anchor) = memrchr(255, &memory[..ptr])
-                    .or_else(|| memrchr(255, &memory[ptr..]).map(|offset| offset + ptr))
-                {
-                    ptr = anchor;
-                    memory[ptr] = 0;
-                } else {
-                    bail!("[boyfriend] infinite loop detected, halting");
+            }
+            Print { ref bytes } => {
+                output(bytes)?;
+            }
+            Preload { ref cells } => {
+                let end = self.origin + cells.len();
+                if self.config.bounds == Bounds::Grow && end > self.memory.len() {
+                    self.memory.resize(end, C::default());
+                }
+                for (cell, &value) in self.memory[self.origin..].iter_mut().zip(cells) {
+                    *cell = C::from_u64(value);
+                }
+            }
+            Scan { stride, target } => {
+                let target = C::from_u64(target);
+                let ptr = self.index(0)?;
+                self.ptr = match self.config.bounds {
+                    Bounds::Wrap => scan(&self.memory, ptr, stride, target),
+                    // past the edge every cell is zero, so the scan either stops on the first
+                    // one or never stops
+                    Bounds::Abort | Bounds::Grow => {
+                        match scan_to_edge(&self.memory, ptr, stride, target) {
+                            Ok(found) => Some(found),
+                            Err(_)
+                                if self.config.bounds == Bounds::Grow && target != C::default() =>
+                            {
+                                None
+                            }
+                            Err(last) => {
+                                self.ptr = last;
+                                Some(self.index(stride)?)
+                            }
+                        }
+                    }
                 }
+                .ok_or(Fault::InfiniteLoop)?;
             }
+            DebugDump => dump(self)?,
         }
 
-        ip += 1;
+        self.ip += 1;
+
+        Ok(())
+    }
+}
+
+/// where a program's output goes -- flushed at the end of every line, before reading input and
+/// when the program exits
+pub struct Writer<'a> {
+    stream: &'a mut dyn Write,
+    buffer: Vec<u8>,
+}
+
+impl<'a> Writer<'a> {
+    /// flush early anyway if a program prints this much without a newline
+    const CAPACITY: usize = 0x2000;
+
+    pub fn new(stream: &'a mut dyn Write) -> Self {
+        Self {
+            stream,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.buffer.extend_from_slice(bytes);
+        if bytes.contains(&b'\n') || self.buffer.len() >= Self::CAPACITY {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.write_all(&self.buffer)?;
+        self.stream.flush()?;
+        self.buffer.clear();
+
+        Ok(())
+    }
+}
+
+/// where `,` reads from -- a stream (usually stdin), or the input after a `!` with `--extensions`
+/// instead if there is any
+pub struct Reader<'a> {
+    stream: &'a mut dyn Read,
+    inline: Option<std::vec::IntoIter<u8>>,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(stream: &'a mut dyn Read, inline: Option<Vec<u8>>) -> Self {
+        Self {
+            stream,
+            inline: inline.map(Vec::into_iter),
+        }
+    }
+
+    /// read a byte of input for `,` -- `None` once the input has run out
+    pub fn read(&mut self) -> std::io::Result<Option<u8>> {
+        if let Some(inline) = &mut self.inline {
+            return Ok(inline.next());
+        }
+
+        let mut byte = [0];
+        loop {
+            match self.stream.read(&mut byte) {
+                Ok(read) => return Ok((read != 0).then_some(byte[0])),
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// print what `#` shows to stderr -- the pointer (counting from the cell the program started on)
+/// and the numbers in the first few cells
+pub fn print_dump(span: Span, pointer: isize, cells: impl Iterator<Item = i128>) {
+    let cells = cells.map(|cell| cell.to_string()).collect::<Vec<_>>();
+    eprintln!("# {span} -- pointer on cell {pointer}: {}", cells.join(" "));
+}
+
+/// run `ir` (which must have its brackets matched), reading `input` for `,` and writing to
+/// `output`. `profile` counts how many times each instruction runs, and reports the hot spots at
+/// the end.
+pub fn interpret(
+    ir: Vec<Node>,
+    config: Config,
+    input: Reader,
+    output: &mut dyn Write,
+    profile: bool,
+) -> Result<(), Error> {
+    match config.cell_width {
+        CellWidth::W8 => run::<u8>(ir, &config, input, output, profile),
+        CellWidth::W16 => run::<u16>(ir, &config, input, output, profile),
+        CellWidth::W32 => run::<u32>(ir, &config, input, output, profile),
+        CellWidth::W64 => run::<u64>(ir, &config, input, output, profile),
+    }
+}
+
+fn run<C: Cell>(
+    ir: Vec<Node>,
+    config: &Config,
+    mut reader: Reader,
+    output: &mut dyn Write,
+    profile: bool,
+) -> Result<(), Error> {
+    let (insts, spans) = unzip(&ir);
+    let mut counts = profile.then(|| vec![0; insts.len()]);
+    let mut fuel = Fuel::new(config);
+    let mut machine = Machine::<C>::new(config);
+    let writer = RefCell::new(Writer::new(output));
+
+    let mut input = || {
+        writer.borrow_mut().flush()?;
+        Ok(reader.read()?)
+    };
+    let mut output = |bytes: &[u8]| Ok(writer.borrow_mut().write(bytes)?);
+    let mut dump = |machine: &Machine<C>| {
+        writer.borrow_mut().flush()?;
+        let pointer = machine.ptr as isize - machine.origin as isize;
+        print_dump(spans[machine.ip], pointer, machine.dump());
+        Ok(())
+    };
+
+    let result = (|| {
+        while machine.ip < insts.len() {
+            let ip = machine.ip;
+            if let Some(counts) = &mut counts {
+                counts[ip] += 1;
+            }
+            if let Some(fuel) = &mut fuel {
+                fuel.burn()
+                    .map_err(|limit| fuel::exhausted(&insts, &spans, ip, limit))?;
+            }
+            machine
+                .step(&insts, &mut input, &mut output, &mut dump)
+                .map_err(|fault| Error::Fault {
+                    fault,
+                    span: spans[ip],
+                })?;
+        }
+
+        Ok(())
+    })();
+    writer.borrow_mut().flush()?;
+
+    if let Some(counts) = counts {
+        profile::report(&insts, &spans, &counts, config);
     }
 
-    Ok(())
+    result
 }
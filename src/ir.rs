@@ -1,269 +1,1060 @@
-use anyhow::bail;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
-use crate::chunk_list::ChunkList;
+use crate::config::{Bounds, CellWidth, Config, Overflow};
+use crate::error::Error;
+use crate::interpret::{Cell, Machine};
 
-#[derive(Clone, Copy, PartialEq)]
+/// every instruction that touches a cell carries an `offset` -- the cell it works on is
+/// `offset` cells away from the pointer. see `sink_shifts` for how these get filled in.
+/// amounts and values are wide enough for any `CellWidth`, and wrap to the cell width unless
+/// cells can overflow (see `Overflow`).
+#[derive(Clone, PartialEq)]
 pub enum IR {
     // brainf*ck instructions
     /// > or <
     Shift { amount: isize },
     /// + or -
-    Arithmetic { amount: i8 },
+    Arithmetic { amount: i64, offset: isize },
     /// [
     LoopStart { end_index: usize },
     /// ]
     LoopEnd { start_index: usize },
     /// ,
-    Input,
+    Input { offset: isize },
     /// .
-    Output,
+    Output { offset: isize },
 
     // idioms
     /// [-]
-    Zero,
+    Zero { offset: isize },
     /// +++++[>++++<-]
-    Multiply { amount: i8, output_offset: isize },
+    Multiply {
+        amount: i64,
+        offset: isize,
+        output_offset: isize,
+    },
     /// +[>+<-]
-    Move { output_offset: isize },
-    /// +[->+]-
-    AnchorRight,
-    /// +[-<+]-
-    AnchorLeft,
+    Move { offset: isize, output_offset: isize },
+    /// +[->+>---<<]
+    MultiplyAdd {
+        offset: isize,
+        /// (output offset, coefficient) pairs
+        targets: Vec<(isize, i64)>,
+    },
+    /// [-]+++++ -- sets a cell to a constant
+    Set { value: u64, offset: isize },
+    /// output from partial evaluation -- prints `bytes`
+    Print { bytes: Vec<u8> },
+    /// a tape image from partial evaluation -- overwrites the first `cells.len()` cells of the
+    /// tape, wherever the pointer is
+    Preload { cells: Vec<u64> },
+    /// [>] or [<<] -- moves the pointer by `stride` until the cell under it is `target`.
+    /// this moves the pointer by an unknown amount, so it doesn't carry an offset
+    Scan { stride: isize, target: u64 },
+    /// # (with `--extensions`) -- prints the pointer and the first `DUMP_CELLS` cells to stderr
+    DebugDump,
 }
 
+/// the number of cells `DebugDump` prints
+pub const DUMP_CELLS: usize = 16;
+
 use IR::*;
 
-/// compile brainf*ck into the intermediate representation (IR)
-pub fn compile(code: String) -> ChunkList<IR> {
+/// a position in the source -- lines and columns start at 1, columns count characters
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// the part of the source an instruction came from, `end` included
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Span {
+    pub start: Location,
+    pub end: Location,
+}
+
+impl Span {
+    /// a single character
+    pub fn at(line: usize, column: usize) -> Self {
+        let location = Location { line, column };
+        Self {
+            start: location,
+            end: location,
+        }
+    }
+
+    /// the smallest span covering both spans, for instructions that were collapsed together
+    pub fn merge(self, other: Span) -> Self {
+        Self {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.start.line, self.start.column)
+    }
+}
+
+/// an instruction and where it came from
+#[derive(Clone, PartialEq)]
+pub struct Node {
+    pub inst: IR,
+    pub span: Span,
+}
+
+/// compile brainf*ck into the intermediate representation (IR) -- `extensions` turns `#` into
+/// `DebugDump`
+pub fn compile(code: String, extensions: bool) -> Vec<Node> {
     let mut ir = Vec::new();
 
-    for inst in code.chars() {
-        match inst {
-            '>' => ir.push(IR::Shift { amount: 1 }),
-            '<' => ir.push(IR::Shift { amount: -1 }),
-            '+' => ir.push(IR::Arithmetic { amount: 1 }),
-            '-' => ir.push(IR::Arithmetic { amount: -1 }),
-            '[' => ir.push(IR::LoopStart {
-                end_index: usize::MAX,
-            }),
-            ']' => ir.push(IR::LoopEnd {
-                start_index: usize::MAX,
-            }),
-            ',' => ir.push(IR::Input),
-            '.' => ir.push(IR::Output),
-            _comment => {}
+    for (line_idx, line) in code.lines().enumerate() {
+        for (column_idx, inst) in line.chars().enumerate() {
+            let inst = match inst {
+                '>' => IR::Shift { amount: 1 },
+                '<' => IR::Shift { amount: -1 },
+                '+' => IR::Arithmetic {
+                    amount: 1,
+                    offset: 0,
+                },
+                '-' => IR::Arithmetic {
+                    amount: -1,
+                    offset: 0,
+                },
+                '[' => IR::LoopStart {
+                    end_index: usize::MAX,
+                },
+                ']' => IR::LoopEnd {
+                    start_index: usize::MAX,
+                },
+                ',' => IR::Input { offset: 0 },
+                '.' => IR::Output { offset: 0 },
+                '#' if extensions => IR::DebugDump,
+                _comment => continue,
+            };
+
+            ir.push(Node {
+                inst,
+                span: Span::at(line_idx + 1, column_idx + 1),
+            });
         }
     }
 
-    ChunkList::new(ir, 2048)
+    ir
 }
 
-pub fn collapse_repeated(ir: &mut ChunkList<IR>) {
-    eprintln!("* collapsing repeated instructions");
+/// split the IR into its instructions and their spans
+pub fn unzip(ir: &[Node]) -> (Vec<IR>, Vec<Span>) {
+    ir.iter().map(|node| (node.inst.clone(), node.span)).unzip()
+}
 
-    let mut pruned = 0;
-    let mut idx = 0;
-    let mut to_prune = Vec::with_capacity(256);
-    while idx < ir.len() {
-        if (idx + 1) < ir.len() {
-            let next = ir[idx + 1];
-            match (&mut ir[idx], next) {
-                (Shift { amount: amt1 }, Shift { amount: amt2 }) => {
-                    *amt1 += amt2;
+/// put instructions and their spans back together
+fn zip(insts: impl IntoIterator<Item = IR>, spans: impl IntoIterator<Item = Span>) -> Vec<Node> {
+    insts
+        .into_iter()
+        .zip(spans)
+        .map(|(inst, span)| Node { inst, span })
+        .collect()
+}
+
+/// an optimization pass over the IR
+pub struct Pass {
+    pub name: &'static str,
+    /// what the pass does, eg. `sinking shifts`
+    pub description: &'static str,
+    /// the lowest `-O` level that enables the pass, `None` if it has to be asked for
+    level: Option<u8>,
+    /// runs the pass, returning the number of instructions pruned
+    run: fn(&mut Vec<Node>, &PassManager, &Config) -> usize,
+}
+
+/// every pass, in the order they're run
+pub const PASSES: [Pass; 6] = [
+    Pass {
+        name: "collapse-repeated",
+        description: "collapsing repeated instructions",
+        level: Some(1),
+        run: |ir, _, config| collapse_repeated(ir, config),
+    },
+    Pass {
+        name: "collapse-idioms",
+        description: "collapsing idioms",
+        level: Some(2),
+        run: |ir, _, config| collapse_idioms(ir, config),
+    },
+    Pass {
+        name: "sink-shifts",
+        description: "sinking shifts",
+        level: Some(2),
+        run: |ir, _, _| sink_shifts(ir),
+    },
+    Pass {
+        name: "prune-dead-code",
+        description: "pruning dead code",
+        level: Some(3),
+        run: |ir, _, config| prune_dead_code(ir, config),
+    },
+    Pass {
+        name: "fuse-sets",
+        description: "fusing sets",
+        level: Some(3),
+        run: |ir, _, config| fuse_sets(ir, config),
+    },
+    Pass {
+        name: "partial-eval",
+        description: "partially evaluating",
+        level: None,
+        run: |ir, manager, config| partial_eval(ir, manager.partial_eval_budget, config),
+    },
+];
+
+/// the most times the passes are repeated when running to a fixed point
+const MAX_ITERATIONS: usize = 16;
+
+/// one run of a pass by `PassManager::run`
+#[derive(Debug, Clone, Copy)]
+pub struct PassRun {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub elapsed: Duration,
+    /// the number of instructions the pass pruned
+    pub pruned: usize,
+}
+
+/// decides which passes run, and runs them
+pub struct PassManager {
+    /// indexed like `PASSES`
+    enabled: [bool; PASSES.len()],
+    /// repeat the passes until they stop pruning instructions
+    pub fixed_point: bool,
+    /// the most steps `partial-eval` may take
+    pub partial_eval_budget: u64,
+}
+
+impl PassManager {
+    /// enable the passes for an `-O` level
+    pub fn new(level: u8) -> Self {
+        Self {
+            enabled: PASSES.map(|pass| pass.level.is_some_and(|min| level >= min)),
+            fixed_point: false,
+            partial_eval_budget: 10_000_000,
+        }
+    }
+
+    fn index(name: &str) -> Result<usize, Error> {
+        PASSES
+            .iter()
+            .position(|pass| pass.name == name)
+            .ok_or_else(|| Error::UnknownPass(name.to_string()))
+    }
+
+    pub fn enable(&mut self, name: &str) -> Result<(), Error> {
+        self.enabled[Self::index(name)?] = true;
+        Ok(())
+    }
 
-                    to_prune.push(idx + 1);
+    pub fn disable(&mut self, name: &str) -> Result<(), Error> {
+        self.enabled[Self::index(name)?] = false;
+        Ok(())
+    }
+
+    pub fn is_enabled(&self, name: &str) -> Result<bool, Error> {
+        Ok(self.enabled[Self::index(name)?])
+    }
+
+    /// run the enabled passes over `ir`, returning what each run of a pass did, in order
+    pub fn run(&self, ir: &mut Vec<Node>, config: &Config) -> Vec<PassRun> {
+        let mut runs = Vec::new();
+
+        for _ in 0..MAX_ITERATIONS {
+            let mut pruned_this_iteration = 0;
+
+            for (idx, pass) in PASSES.iter().enumerate() {
+                if !self.enabled[idx] {
+                    continue;
                 }
-                (Arithmetic { amount: amt1 }, Arithmetic { amount: amt2 })
-                    if *amt1 + amt2 < i8::MAX =>
-                {
-                    *amt1 += amt2;
 
+                let start = Instant::now();
+                let pruned = (pass.run)(ir, self, config);
+                runs.push(PassRun {
+                    name: pass.name,
+                    description: pass.description,
+                    elapsed: start.elapsed(),
+                    pruned,
+                });
+                pruned_this_iteration += pruned;
+            }
+
+            if !self.fixed_point || pruned_this_iteration == 0 {
+                break;
+            }
+        }
+
+        runs
+    }
+}
+
+pub fn collapse_repeated(ir: &mut Vec<Node>, config: &Config) -> usize {
+    let width = config.cell_width;
+    let wraps = config.overflow == Overflow::Wrap;
+    let mut collapsed: Vec<Node> = Vec::with_capacity(ir.len());
+    for node in ir.iter() {
+        if let Some(last) = collapsed.last_mut() {
+            match (&mut last.inst, &node.inst) {
+                (Shift { amount: amt1 }, Shift { amount: amt2 }) => {
+                    *amt1 += amt2;
+                    last.span = last.span.merge(node.span);
+                    if *amt1 == 0 {
+                        collapsed.pop();
+                    }
+                    continue;
+                }
+                // a cell that can overflow might do it halfway through `+++---`, so only runs
+                // going the same way are collapsed
+                (
+                    Arithmetic {
+                        amount: amt1,
+                        offset: ofs1,
+                    },
+                    Arithmetic {
+                        amount: amt2,
+                        offset: ofs2,
+                    },
+                ) if ofs1 == ofs2 && (wraps || amt1.signum() == amt2.signum()) => {
+                    *amt1 = match wraps {
+                        true => width.wrap_signed(amt1.wrapping_add(*amt2)),
+                        false => amt1.saturating_add(*amt2),
+                    };
+                    last.span = last.span.merge(node.span);
                     if *amt1 == 0 {
-                        to_prune.push(idx);
+                        collapsed.pop();
                     }
-                    to_prune.push(idx + 1);
+                    continue;
                 }
-                _ => idx += 1,
+                _ => {}
             }
-        } else {
-            break;
         }
 
-        pruned += to_prune.len();
-        for prune_idx in to_prune.drain(0..).rev() {
-            ir.remove(prune_idx);
-        }
+        collapsed.push(node.clone());
     }
 
-    eprintln!("* success, pruned {pruned} instructions");
-}
+    let pruned = ir.len() - collapsed.len();
+    *ir = collapsed;
 
-pub fn collapse_idioms(ir: &mut ChunkList<IR>) {
-    eprintln!("* collapsing idioms");
+    pruned
+}
 
+pub fn collapse_idioms(ir: &mut Vec<Node>, config: &Config) -> usize {
+    let width = config.cell_width;
+    let wraps = config.overflow == Overflow::Wrap;
+    let mut collapsed = Vec::with_capacity(ir.len());
     let mut idx = 0;
     let mut pruned = 0;
 
     while idx < ir.len() {
-        if (idx + 5) < ir.len() {
-            match (
-                ir[idx],
-                ir[idx + 1],
-                ir[idx + 2],
-                ir[idx + 3],
-                ir[idx + 4],
-                ir[idx + 5],
-            ) {
-                (
-                    LoopStart { .. },
-                    Shift { amount: ofs1 },
-                    Arithmetic { amount: 1 },
-                    Shift { amount: ofs2 },
-                    Arithmetic { amount: -1 },
-                    LoopEnd { .. },
-                ) if ofs1 == -ofs2 => {
-                    ir[idx] = IR::Move {
-                        output_offset: ofs1,
-                    };
-                    ir.remove(idx + 5);
-                    ir.remove(idx + 4);
-                    ir.remove(idx + 3);
-                    ir.remove(idx + 2);
-                    ir.remove(idx + 1);
-                    pruned += 5;
+        if let LoopStart { .. } = ir[idx].inst
+            && let Some((len, idiom)) = balanced_loop(ir, idx, config)
+        {
+            collapsed.push(Node {
+                inst: idiom,
+                span: ir[idx].span.merge(ir[idx + len - 1].span),
+            });
+            idx += len;
+            pruned += len - 1;
+            continue;
+        }
+        // +[->+]- and friends -- only the first cell gets changed and the cell that's found gets
+        // zeroed, so the loop is run once at most. keep it as a loop for the zero check. the cell
+        // that's found is the one that overflows, so it has to be searched for the slow way when
+        // cells can overflow.
+        if wraps
+            && let Some(window) = ir.get(idx..idx + 5)
+            && let (
+                LoopStart { .. },
+                Arithmetic {
+                    amount: amt1,
+                    offset: 0,
+                },
+                Shift { amount: stride },
+                Arithmetic {
+                    amount: amt2,
+                    offset: 0,
+                },
+                LoopEnd { .. },
+            ) = (
+                &window[0].inst,
+                &window[1].inst,
+                &window[2].inst,
+                &window[3].inst,
+                &window[4].inst,
+            )
+            && width.wrap(*amt1) != 0
+            && width.wrap(amt1.wrapping_add(*amt2)) == 0
+        {
+            let scan = Node {
+                inst: Scan {
+                    stride: *stride,
+                    target: width.wrap(*amt1),
+                },
+                span: window[2].span,
+            };
+            collapsed.extend_from_slice(&window[..3]);
+            collapsed.push(scan);
+            collapsed.extend_from_slice(&window[3..]);
+            idx += 5;
+            continue;
+        }
+        if let Some(window) = ir.get(idx..idx + 3) {
+            let idiom = match (&window[0].inst, &window[1].inst, &window[2].inst) {
+                (LoopStart { .. }, Shift { amount: stride }, LoopEnd { .. }) => Some(Scan {
+                    stride: *stride,
+                    target: 0,
+                }),
+                // `[+]` or `[--]` only reach zero by wrapping around
+                (LoopStart { .. }, Arithmetic { offset: 0, amount }, LoopEnd { .. })
+                    if wraps || *amount == -1 =>
+                {
+                    Some(Zero { offset: 0 })
                 }
-                // TODO: convert Arithmetic { amount: -1 } to Arithmetic { amount: amt2 } or something
-                (
-                    LoopStart { .. },
-                    Shift { amount: ofs1 },
-                    Arithmetic { amount: amt @ 0.. },
-                    Shift { amount: ofs2 },
-                    Arithmetic { amount: -1 },
-                    LoopEnd { .. },
-                ) if ofs1 == -ofs2 => {
-                    ir[idx] = IR::Multiply {
-                        amount: amt,
-                        output_offset: ofs1,
-                    };
-                    ir.remove(idx + 5);
-                    ir.remove(idx + 4);
-                    ir.remove(idx + 3);
-                    ir.remove(idx + 2);
-                    ir.remove(idx + 1);
-                    pruned += 5;
+                _ => None,
+            };
+            if let Some(idiom) = idiom {
+                collapsed.push(Node {
+                    inst: idiom,
+                    span: window[0].span.merge(window[2].span),
+                });
+                idx += 3;
+                pruned += 2;
+                continue;
+            }
+        }
+
+        collapsed.push(ir[idx].clone());
+        idx += 1;
+    }
+
+    *ir = collapsed;
+
+    pruned
+}
+
+/// analyze the loop starting at `start` -- if it has no net pointer movement, steps its origin
+/// cell by exactly -1 and does nothing but arithmetic, it's a multiply loop like `[->+>++<<]`.
+/// returns the length of the loop and the idiom it collapses into.
+fn balanced_loop(ir: &[Node], start: usize, config: &Config) -> Option<(usize, IR)> {
+    let width = config.cell_width;
+    let wraps = config.overflow == Overflow::Wrap;
+    let len = ir.len();
+    let mut position = 0;
+    let mut deltas: Vec<(isize, i64)> = Vec::new();
+    let mut idx = start + 1;
+
+    loop {
+        if idx >= len {
+            return None;
+        }
+
+        match ir[idx].inst {
+            Shift { amount } => position += amount,
+            Arithmetic { amount, offset } => {
+                let cell = position + offset;
+                match deltas.iter_mut().find(|(target, _)| *target == cell) {
+                    Some((_, delta)) if wraps => {
+                        *delta = width.wrap_signed(delta.wrapping_add(amount))
+                    }
+                    Some((_, delta)) => *delta = delta.saturating_add(amount),
+                    None if wraps => deltas.push((cell, width.wrap_signed(amount))),
+                    None => deltas.push((cell, amount)),
                 }
-                _ => {}
             }
+            LoopEnd { .. } => break,
+            _ => return None,
         }
-        if (idx + 4) < ir.len() {
-            match (ir[idx], ir[idx + 1], ir[idx + 2], ir[idx + 3], ir[idx + 4]) {
-                // TODO: implement non-255 anchors
-                (
-                    LoopStart { .. },
-                    Arithmetic { amount: -1 },
-                    Shift {
-                        amount: dir @ (1 | -1),
-                    },
-                    Arithmetic { amount: 1 },
-                    LoopEnd { .. },
-                ) => {
-                    if dir == 1 {
-                        ir[idx] = IR::AnchorRight;
-                    } else if dir == -1 {
-                        ir[idx] = IR::AnchorLeft;
+
+        idx += 1;
+    }
+
+    if position != 0 {
+        return None;
+    }
+
+    let origin = deltas.iter().position(|(target, _)| *target == 0)?;
+    if deltas.remove(origin).1 != -1 {
+        return None;
+    }
+    deltas.retain(|(_, coefficient)| *coefficient != 0);
+
+    let idiom = match deltas[..] {
+        // `[-]` is left for the zeroing idiom
+        [] => return None,
+        [(output_offset, 1)] => Move {
+            offset: 0,
+            output_offset,
+        },
+        [(output_offset, amount)] => Multiply {
+            amount,
+            offset: 0,
+            output_offset,
+        },
+        _ => MultiplyAdd {
+            offset: 0,
+            targets: deltas,
+        },
+    };
+
+    Some((idx - start + 1, idiom))
+}
+
+/// defer pointer shifts to the end of each basic block (loop boundaries and scans),
+/// turning the shifts in between into offsets on the instructions themselves
+pub fn sink_shifts(ir: &mut Vec<Node>) -> usize {
+    let mut sunk = Vec::with_capacity(ir.len());
+    let mut pending = 0;
+    // where the pending shifts came from
+    let mut pending_span: Option<Span> = None;
+
+    for node in ir.iter() {
+        let inst = match node.inst {
+            Shift { amount } => {
+                pending += amount;
+                pending_span = Some(pending_span.map_or(node.span, |span| span.merge(node.span)));
+                continue;
+            }
+            Arithmetic { amount, offset } => Arithmetic {
+                amount,
+                offset: offset + pending,
+            },
+            Input { offset } => Input {
+                offset: offset + pending,
+            },
+            Output { offset } => Output {
+                offset: offset + pending,
+            },
+            Zero { offset } => Zero {
+                offset: offset + pending,
+            },
+            Multiply {
+                amount,
+                offset,
+                output_offset,
+            } => Multiply {
+                amount,
+                offset: offset + pending,
+                output_offset,
+            },
+            Move {
+                offset,
+                output_offset,
+            } => Move {
+                offset: offset + pending,
+                output_offset,
+            },
+            MultiplyAdd {
+                offset,
+                ref targets,
+            } => MultiplyAdd {
+                offset: offset + pending,
+                targets: targets.clone(),
+            },
+            Set { value, offset } => Set {
+                value,
+                offset: offset + pending,
+            },
+            Print { .. } | Preload { .. } => node.inst.clone(),
+            LoopStart { .. } | LoopEnd { .. } | Scan { .. } | DebugDump => {
+                if let Some(span) = pending_span.take()
+                    && pending != 0
+                {
+                    sunk.push(Node {
+                        inst: Shift { amount: pending },
+                        span,
+                    });
+                }
+                pending = 0;
+                node.inst.clone()
+            }
+        };
+
+        sunk.push(Node {
+            inst,
+            span: node.span,
+        });
+    }
+
+    if let Some(span) = pending_span
+        && pending != 0
+    {
+        sunk.push(Node {
+            inst: Shift { amount: pending },
+            span,
+        });
+    }
+
+    let pruned = ir.len() - sunk.len();
+    *ir = sunk;
+
+    pruned
+}
+
+/// what's known about the cells around the pointer while walking through the IR
+struct KnownCells {
+    /// `None` means the cell was changed to something unknown
+    values: HashMap<isize, Option<u64>>,
+    /// cells that aren't in `values` are zero until the first loop
+    untouched_zero: bool,
+    config: Config,
+}
+
+impl KnownCells {
+    fn program_start(config: &Config) -> Self {
+        Self {
+            values: HashMap::new(),
+            untouched_zero: true,
+            config: *config,
+        }
+    }
+
+    /// the value of the cell `offset` cells away from the pointer after adding `amount` to it --
+    /// `None` if it isn't known or it would overflow
+    fn add(&self, offset: isize, amount: i64) -> Option<u64> {
+        let value = self.get(offset)?;
+        self.config
+            .fit(self.config.cell_value(value) + amount as i128)
+    }
+
+    fn get(&self, offset: isize) -> Option<u64> {
+        match self.values.get(&offset) {
+            Some(value) => *value,
+            None if self.untouched_zero => Some(0),
+            None => None,
+        }
+    }
+
+    fn set(&mut self, offset: isize, value: Option<u64>) {
+        self.values.insert(offset, value);
+    }
+
+    fn forget_all(&mut self) {
+        self.values.clear();
+        self.untouched_zero = false;
+    }
+
+    /// apply the effects of `inst`
+    fn update(&mut self, inst: &IR) {
+        match *inst {
+            Shift { amount } => {
+                self.values = self
+                    .values
+                    .drain()
+                    .map(|(offset, value)| (offset - amount, value))
+                    .collect();
+            }
+            Arithmetic { amount, offset } => self.set(offset, self.add(offset, amount)),
+            Zero { offset } => self.set(offset, Some(0)),
+            Set { value, offset } => self.set(offset, Some(value)),
+            Input { offset } => self.set(offset, None),
+            Output { .. } | Print { .. } | DebugDump => {}
+            // the pointer could be anywhere
+            Preload { .. } => self.forget_all(),
+            Multiply {
+                offset,
+                output_offset,
+                ..
+            }
+            | Move {
+                offset,
+                output_offset,
+            } => {
+                if self.get(offset) != Some(0) {
+                    self.set(offset + output_offset, None);
+                }
+                self.set(offset, Some(0));
+            }
+            MultiplyAdd {
+                offset,
+                ref targets,
+            } => {
+                if self.get(offset) != Some(0) {
+                    for &(output_offset, _) in targets {
+                        self.set(offset + output_offset, None);
                     }
-                    ir.remove(idx + 4);
-                    ir.remove(idx + 3);
-                    ir.remove(idx + 2);
-                    ir.remove(idx + 1);
-                    pruned += 4;
                 }
+                self.set(offset, Some(0));
+            }
+            LoopStart { .. } => self.forget_all(),
+            LoopEnd { .. } => {
+                self.forget_all();
+                self.set(0, Some(0));
+            }
+            Scan { target, .. } => {
+                self.forget_all();
+                self.set(0, Some(target));
+            }
+        }
+    }
+}
+
+/// remove loops that can never run and `Zero`s and `Set`s that don't change anything
+pub fn prune_dead_code(ir: &mut Vec<Node>, config: &Config) -> usize {
+    let mut pruned = Vec::with_capacity(ir.len());
+    let mut known = KnownCells::program_start(config);
+    // how deep we are into a loop that's being removed
+    let mut dead_depth = 0;
+
+    for node in ir.iter() {
+        let inst = &node.inst;
+        if dead_depth > 0 {
+            match inst {
+                LoopStart { .. } => dead_depth += 1,
+                LoopEnd { .. } => dead_depth -= 1,
                 _ => {}
             }
+            continue;
         }
-        if (idx + 2) < ir.len() {
-            if let (LoopStart { .. }, Arithmetic { .. }, LoopEnd { .. }) =
-                (ir[idx], ir[idx + 1], ir[idx + 2])
-            {
-                ir[idx] = IR::Zero;
-                ir.remove(idx + 2);
-                ir.remove(idx + 1);
-                pruned += 2;
+
+        match *inst {
+            LoopStart { .. } if known.get(0) == Some(0) => {
+                dead_depth = 1;
+                continue;
             }
+            Zero { offset } if known.get(offset) == Some(0) => continue,
+            Set { value, offset } if known.get(offset) == Some(value) => continue,
+            _ => {}
         }
 
-        idx += 1;
+        known.update(inst);
+        pruned.push(node.clone());
     }
 
-    eprintln!("* success, pruned {pruned} instructions");
-}
+    let count = ir.len() - pruned.len();
+    *ir = pruned;
 
-/// match loops (only used by the interpreter)
-pub fn match_brackets(ir: &mut ChunkList<IR>) -> anyhow::Result<()> {
-    eprintln!("* matching brackets");
+    count
+}
 
-    'matching: for idx in 0..ir.len() {
-        match ir[idx] {
-            LoopStart { .. } => {
-                let mut counter = 1;
+/// turn arithmetic on cells with known values into `Set`s, folding them into the `Set` or `Zero`
+/// that made them known. cells are known at the start of the program (all zero), after a loop
+/// exits (zero) and after a scan (the scan target).
+pub fn fuse_sets(ir: &mut Vec<Node>, config: &Config) -> usize {
+    let mut fused = Vec::with_capacity(ir.len());
+    let mut known = KnownCells::program_start(config);
+    // the `Set` that last wrote each cell, if nothing has read it since
+    let mut last_set: HashMap<isize, usize> = HashMap::new();
 
-                for end_index_maybe in idx + 1.. {
-                    if end_index_maybe >= ir.len() {
-                        break;
+    for node in ir.iter() {
+        let inst = &node.inst;
+        let (value, offset) = match *inst {
+            Arithmetic { amount, offset } if let Some(value) = known.add(offset, amount) => {
+                (value, offset)
+            }
+            // zeroing a negative signed cell overflows, so the `Zero` has to stay unless the cell
+            // is known not to be negative
+            Zero { offset }
+                if config.overflow != Overflow::Signed
+                    || known
+                        .get(offset)
+                        .is_some_and(|value| config.cell_value(value) >= 0) =>
+            {
+                (0, offset)
+            }
+            Set { value, offset } => (value, offset),
+            _ => {
+                // anything else that touches a cell might read it
+                match *inst {
+                    Input { offset }
+                    | Output { offset }
+                    | Arithmetic { offset, .. }
+                    | Zero { offset } => {
+                        last_set.remove(&offset);
                     }
-
-                    match ir[end_index_maybe] {
-                        LoopStart { .. } => counter += 1,
-                        LoopEnd { .. } => {
-                            counter -= 1;
-
-                            if counter == 0 {
-                                let LoopStart { ref mut end_index } = ir[idx] else {
-                                    unreachable!()
-                                };
-                                *end_index = end_index_maybe;
-                                let LoopEnd {
-                                    ref mut start_index,
-                                } = ir[end_index_maybe]
-                                else {
-                                    unreachable!()
-                                };
-                                *start_index = idx;
-                                continue 'matching;
-                            }
+                    Multiply {
+                        offset,
+                        output_offset,
+                        ..
+                    }
+                    | Move {
+                        offset,
+                        output_offset,
+                    } => {
+                        last_set.remove(&offset);
+                        last_set.remove(&(offset + output_offset));
+                    }
+                    MultiplyAdd {
+                        offset,
+                        ref targets,
+                    } => {
+                        last_set.remove(&offset);
+                        for &(output_offset, _) in targets {
+                            last_set.remove(&(offset + output_offset));
                         }
-                        _ => {}
                     }
+                    _ => last_set.clear(),
                 }
 
-                unreachable!()
+                known.update(inst);
+                fused.push(node.clone());
+                continue;
             }
+        };
+
+        known.set(offset, Some(value));
+        if let Some(&idx) = last_set.get(&offset) {
+            let set: &mut Node = &mut fused[idx];
+            set.inst = Set { value, offset };
+            set.span = set.span.merge(node.span);
+        } else {
+            last_set.insert(offset, fused.len());
+            fused.push(Node {
+                inst: Set { value, offset },
+                span: node.span,
+            });
+        }
+    }
+
+    let pruned = ir.len() - fused.len();
+    *ir = fused;
+
+    pruned
+}
+
+/// match loops (used by the interpreters, and by cranelift to split loops off)
+pub fn match_brackets(ir: &mut Vec<Node>) {
+    let (mut insts, spans) = unzip(ir);
+    link_brackets(&mut insts);
+    *ir = zip(insts, spans);
+}
+
+/// fill in the `end_index` and `start_index` of every loop
+fn link_brackets(insts: &mut [IR]) {
+    let mut starts = Vec::new();
+
+    for idx in 0..insts.len() {
+        match insts[idx] {
+            LoopStart { .. } => starts.push(idx),
             LoopEnd {
-                start_index: usize::MAX,
+                ref mut start_index,
             } => {
-                unreachable!()
+                let Some(start) = starts.pop() else {
+                    unreachable!()
+                };
+                *start_index = start;
+                insts[start] = LoopStart { end_index: idx };
             }
             _ => {}
         }
     }
+}
 
-    Ok(())
+/// run the program at compile time until its first `,` or `#` or until `budget` steps have been taken,
+/// then replace everything before the last top-level instruction it reached with a precomputed
+/// tape image and the output produced up to that point
+pub fn partial_eval(ir: &mut Vec<Node>, budget: u64, config: &Config) -> usize {
+    // a tape image can't hold cells to the left of where the program started, so evaluation
+    // stops before the tape would grow
+    let config = Config {
+        bounds: match config.bounds {
+            Bounds::Grow => Bounds::Abort,
+            bounds => bounds,
+        },
+        ..*config
+    };
+    match config.cell_width {
+        CellWidth::W8 => partial_eval_cells::<u8>(ir, budget, &config),
+        CellWidth::W16 => partial_eval_cells::<u16>(ir, budget, &config),
+        CellWidth::W32 => partial_eval_cells::<u32>(ir, budget, &config),
+        CellWidth::W64 => partial_eval_cells::<u64>(ir, budget, &config),
+    }
 }
 
-/// verify brackets
-pub fn verify(source: impl AsRef<str>) -> anyhow::Result<()> {
-    let mut balanced = 0isize;
+fn partial_eval_cells<C: Cell>(ir: &mut Vec<Node>, budget: u64, config: &Config) -> usize {
+    let (mut insts, mut spans) = unzip(ir);
+    link_brackets(&mut insts);
 
-    for c in source.as_ref().chars() {
-        if c == '[' { balanced += 1; }
-        else if c == ']' { balanced -= 1; }
+    // top-level instructions run once at most, so the state when one is reached can be recomputed
+    let mut top_level = Vec::with_capacity(insts.len() + 1);
+    let mut depth = 0;
+    for inst in insts.iter() {
+        top_level.push(depth == 0);
+        match inst {
+            LoopStart { .. } => depth += 1,
+            LoopEnd { .. } => depth -= 1,
+            _ => {}
+        }
     }
+    top_level.push(true);
+
+    let mut machine = Machine::<C>::new(config);
+    let mut output = Vec::new();
+    let mut steps = 0;
+    // (ip, steps, output length) of the last top-level instruction reached
+    let mut checkpoint = (0, 0, 0);
 
-    if balanced > 0 {
-        bail!("{} unmatched opening bracket(s) (`[`)", balanced);
-    } else if balanced < 0 {
-        bail!("{} unmatched closing bracket(s) (`]`)", balanced.abs());
+    loop {
+        if top_level[machine.ip] {
+            checkpoint = (machine.ip, steps, output.len());
+        }
+        if machine.ip == insts.len()
+            || steps == budget
+            || matches!(insts[machine.ip], Input { .. } | DebugDump)
+        {
+            break;
+        }
+
+        let result = machine.step(
+            &insts,
+            &mut || unreachable!(),
+            &mut |bytes| {
+                output.extend_from_slice(bytes);
+                Ok(())
+            },
+            &mut |_| unreachable!(),
+        );
+        if result.is_err() {
+            break;
+        }
+        steps += 1;
+    }
+
+    let (ip, checkpoint_steps, output_len) = checkpoint;
+    if ip == 0 {
+        return 0;
+    }
+
+    if checkpoint_steps != steps {
+        machine = Machine::new(config);
+        for _ in 0..checkpoint_steps {
+            machine
+                .step(
+                    &insts,
+                    &mut || unreachable!(),
+                    &mut |_| Ok(()),
+                    &mut |_| unreachable!(),
+                )
+                .expect("the same steps succeeded before");
+        }
+    }
+
+    // everything that was evaluated gets blamed on the whole prefix
+    let span = spans[..ip]
+        .iter()
+        .copied()
+        .reduce(Span::merge)
+        .expect("at least one instruction was evaluated");
+    let mut evaluated = Vec::with_capacity(insts.len() - ip + 3);
+    if output_len > 0 {
+        evaluated.push(Print {
+            bytes: output[..output_len].to_vec(),
+        });
+    }
+    // the state doesn't matter if the whole program was evaluated
+    if ip < insts.len() {
+        if let Some(last) = machine
+            .memory
+            .iter()
+            .rposition(|&cell| cell != C::default())
+        {
+            evaluated.push(Preload {
+                cells: machine.memory[..=last]
+                    .iter()
+                    .map(|cell| cell.to_u64())
+                    .collect(),
+            });
+        }
+        if machine.ptr != 0 {
+            evaluated.push(Shift {
+                amount: machine.ptr as isize,
+            });
+        }
+    }
+    let spans = std::iter::repeat_n(span, evaluated.len()).chain(spans.drain(ip..));
+    evaluated.extend(insts.drain(ip..));
+    let evaluated = zip(evaluated, spans);
+
+    let pruned = ir.len().saturating_sub(evaluated.len());
+    *ir = evaluated;
+
+    pruned
+}
+
+/// split a program written with `--extensions` into its code and the input after the first `!`
+pub fn split_input(source: &str) -> (&str, Option<&str>) {
+    match source.split_once('!') {
+        Some((code, input)) => (code, Some(input)),
+        None => (source, None),
+    }
+}
+
+/// verify brackets -- `Error::Brackets` has the location of every unmatched one
+pub fn verify(source: &str) -> Result<(), Error> {
+    // the `[`s that haven't been closed yet
+    let mut opening = Vec::new();
+    let mut closing = Vec::new();
+
+    for (line_idx, line) in source.lines().enumerate() {
+        for (column_idx, c) in line.chars().enumerate() {
+            let span = Span::at(line_idx + 1, column_idx + 1);
+            if c == '[' {
+                opening.push(span);
+            } else if c == ']' && opening.pop().is_none() {
+                closing.push(span);
+            }
+        }
+    }
+
+    match opening.is_empty() && closing.is_empty() {
+        true => Ok(()),
+        false => Err(Error::Brackets { opening, closing }),
+    }
+}
+
+/// show where `span` is in the source, rustc style, with `context` more lines either side:
+///
+/// ```text
+///   --> hello.bf:3:9
+///    |
+///  3 | ++[>+<-]]
+///    |         ^
+/// ```
+pub fn excerpt(path: &Path, source: &str, span: Span, context: usize) -> String {
+    let Location { line, column } = span.start;
+    let lines = source.lines().collect::<Vec<_>>();
+    let text = lines.get(line - 1).copied().unwrap_or_default();
+    let first = line.saturating_sub(context).max(1);
+    let last = (line + context).min(lines.len()).max(line);
+    let width = last.to_string().len();
+
+    // spans over several lines are underlined to the end of the first one
+    let end = if span.end.line == line {
+        span.end.column
+    } else {
+        text.chars().count()
+    };
+    // copy tabs so the carets line up however wide they are
+    let padding = text
+        .chars()
+        .take(column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect::<String>();
+    let carets = "^".repeat(end.saturating_sub(column) + 1);
+
+    let mut excerpt = format!(
+        "{:width$}--> {}:{span}\n{:width$} |\n",
+        "",
+        path.display(),
+        ""
+    );
+    for number in first..=last {
+        let text = lines.get(number - 1).copied().unwrap_or_default();
+        excerpt += &format!("{number:>width$} | {text}\n");
+        if number == line {
+            excerpt += &format!("{:width$} | {padding}{carets}\n", "");
+        }
     }
 
-    Ok(())
+    excerpt
 }
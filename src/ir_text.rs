@@ -0,0 +1,316 @@
+use std::fmt::{self, Display};
+use std::io::Write;
+
+use anyhow::{Context, bail};
+
+use crate::config::CellWidth;
+use crate::error::Error;
+use crate::ir::IR::{self, *};
+use crate::ir::{Location, Node, Span};
+
+/// the textual form of the IR, one instruction per line. offsets from the pointer are written as
+/// `@+N`, and the targets of multiplies are written relative to the cell being multiplied, eg.
+/// `mul @+1: +3 @+2` adds 3 times the cell after the pointer to the cell 3 after the pointer.
+impl Display for IR {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Shift { amount } => write!(f, "shift {amount:+}"),
+            Arithmetic { amount, offset } => write!(f, "add {amount:+} @{offset:+}"),
+            LoopStart { .. } => write!(f, "loop"),
+            LoopEnd { .. } => write!(f, "end"),
+            Input { offset } => write!(f, "in @{offset:+}"),
+            Output { offset } => write!(f, "out @{offset:+}"),
+            Zero { offset } => write!(f, "zero @{offset:+}"),
+            Multiply {
+                amount,
+                offset,
+                output_offset,
+            } => write!(f, "mul @{offset:+}: {amount:+} @{output_offset:+}"),
+            Move {
+                offset,
+                output_offset,
+            } => write!(f, "move @{offset:+}: @{output_offset:+}"),
+            MultiplyAdd { offset, targets } => {
+                write!(f, "muladd @{offset:+}:")?;
+                for (idx, (output_offset, coefficient)) in targets.iter().enumerate() {
+                    let separator = if idx == 0 { "" } else { "," };
+                    write!(f, "{separator} {coefficient:+} @{output_offset:+}")?;
+                }
+                Ok(())
+            }
+            Set { value, offset } => write!(f, "set {value} @{offset:+}"),
+            Print { bytes } => write!(f, "print \"{}\"", bytes.escape_ascii()),
+            Preload { cells } => {
+                write!(f, "preload")?;
+                for cell in cells {
+                    write!(f, " {cell:02x}")?;
+                }
+                Ok(())
+            }
+            Scan { stride, target } => write!(f, "scan {stride:+} until {target}"),
+            DebugDump => write!(f, "dump"),
+        }
+    }
+}
+
+/// write the IR as text, indenting loop bodies
+pub fn write(ir: &[Node], writer: &mut impl Write) -> std::io::Result<()> {
+    let mut depth = 0;
+
+    for Node { inst, .. } in ir.iter() {
+        if let LoopEnd { .. } = inst {
+            depth -= 1;
+        }
+        writeln!(writer, "{:indent$}{inst}", "", indent = depth * 2)?;
+        if let LoopStart { .. } = inst {
+            depth += 1;
+        }
+    }
+
+    Ok(())
+}
+
+/// parse IR written by `write` -- indentation is ignored, and so is anything after a `#` outside
+/// a string. the span of each instruction is its line in the text. a `preload` that doesn't fit
+/// on a tape of `tape_size` cells is an error, and so is a value that doesn't fit in a cell of
+/// `cell_width`.
+pub fn parse(text: &str, tape_size: usize, cell_width: CellWidth) -> Result<Vec<Node>, Error> {
+    let mut ir = Vec::new();
+    let mut depth = 0usize;
+
+    for (line_number, full_line) in text.lines().enumerate() {
+        let line = strip_comment(full_line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let inst = parse_line(line).map_err(|err| Error::Parse {
+            line: line_number + 1,
+            message: format!("`{line}`: {err:#}"),
+        })?;
+        let too_big = match inst {
+            Set { value, .. } | Scan { target: value, .. } => Some(value),
+            Preload { ref cells } => cells.iter().copied().max(),
+            _ => None,
+        }
+        .filter(|&value| value > cell_width.max());
+        if let Some(value) = too_big {
+            return Err(Error::Parse {
+                line: line_number + 1,
+                message: format!("{value} doesn't fit in {}-bit cells", cell_width.bits()),
+            });
+        }
+        match inst {
+            Preload { ref cells } if cells.len() > tape_size => {
+                return Err(Error::Parse {
+                    line: line_number + 1,
+                    message: format!(
+                        "`preload` has {} cells, but the tape only has {tape_size}",
+                        cells.len()
+                    ),
+                });
+            }
+            LoopStart { .. } => depth += 1,
+            LoopEnd { .. } => {
+                let Some(new_depth) = depth.checked_sub(1) else {
+                    return Err(Error::Parse {
+                        line: line_number + 1,
+                        message: "`end` without a `loop`".to_string(),
+                    });
+                };
+                depth = new_depth;
+            }
+            _ => {}
+        }
+
+        let indent = full_line.len() - full_line.trim_start().len();
+        let start = full_line[..indent].chars().count() + 1;
+        ir.push(Node {
+            inst,
+            span: Span {
+                start: Location {
+                    line: line_number + 1,
+                    column: start,
+                },
+                end: Location {
+                    line: line_number + 1,
+                    column: start + line.chars().count() - 1,
+                },
+            },
+        });
+    }
+
+    if depth > 0 {
+        return Err(Error::Parse {
+            line: text.lines().count(),
+            message: format!("{depth} `loop`(s) without an `end`"),
+        });
+    }
+
+    Ok(ir)
+}
+
+/// everything before the first `#` that isn't in a quoted string (`#` can show up in printed
+/// strings)
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for (idx, byte) in line.bytes().enumerate() {
+        match byte {
+            _ if escaped => escaped = false,
+            b'\\' if quoted => escaped = true,
+            b'"' => quoted = !quoted,
+            b'#' if !quoted => return &line[..idx],
+            _ => {}
+        }
+    }
+
+    line
+}
+
+fn parse_line(line: &str) -> anyhow::Result<IR> {
+    let (op, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let args = rest.split_whitespace().collect::<Vec<_>>();
+
+    let inst = match (op, &args[..]) {
+        ("shift", [amount]) => Shift {
+            amount: number(amount)?,
+        },
+        ("add", [amount, offset]) => Arithmetic {
+            amount: number(amount)?,
+            offset: cell(offset)?,
+        },
+        ("loop", []) => LoopStart {
+            end_index: usize::MAX,
+        },
+        ("end", []) => LoopEnd {
+            start_index: usize::MAX,
+        },
+        ("in", [offset]) => Input {
+            offset: cell(offset)?,
+        },
+        ("out", [offset]) => Output {
+            offset: cell(offset)?,
+        },
+        ("zero", [offset]) => Zero {
+            offset: cell(offset)?,
+        },
+        ("mul", [offset, amount, output_offset]) => Multiply {
+            amount: number(amount)?,
+            offset: source(offset)?,
+            output_offset: cell(output_offset)?,
+        },
+        ("move", [offset, output_offset]) => Move {
+            offset: source(offset)?,
+            output_offset: cell(output_offset)?,
+        },
+        ("muladd", [offset, ..]) => {
+            let (_, targets) = rest
+                .split_once(':')
+                .context("expected `:` after the source")?;
+            let targets = targets
+                .split(',')
+                .map(
+                    |target| match target.split_whitespace().collect::<Vec<_>>()[..] {
+                        [coefficient, output_offset] => {
+                            Ok((cell(output_offset)?, number(coefficient)?))
+                        }
+                        _ => bail!("expected `COEFFICIENT @OFFSET`, found `{}`", target.trim()),
+                    },
+                )
+                .collect::<anyhow::Result<Vec<_>>>()?;
+
+            MultiplyAdd {
+                offset: source(offset)?,
+                targets,
+            }
+        }
+        ("set", [value, offset]) => Set {
+            value: number(value)?,
+            offset: cell(offset)?,
+        },
+        ("print", [_, ..]) => Print {
+            bytes: unescape(rest)?,
+        },
+        ("preload", [_, ..]) => Preload {
+            cells: args
+                .iter()
+                .map(|cell| {
+                    u64::from_str_radix(cell, 16).with_context(|| format!("bad hex cell `{cell}`"))
+                })
+                .collect::<anyhow::Result<_>>()?,
+        },
+        ("scan", [stride, "until", target]) => Scan {
+            stride: number(stride)?,
+            target: number(target)?,
+        },
+        ("dump", []) => DebugDump,
+        _ => bail!("unknown instruction or wrong arguments"),
+    };
+
+    Ok(inst)
+}
+
+fn number<T: std::str::FromStr>(token: &str) -> anyhow::Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    token
+        .parse()
+        .with_context(|| format!("bad number `{token}`"))
+}
+
+/// `@+N`
+fn cell(token: &str) -> anyhow::Result<isize> {
+    number(
+        token
+            .strip_prefix('@')
+            .with_context(|| format!("expected an offset like `@+1`, found `{token}`"))?,
+    )
+}
+
+/// `@+N:`
+fn source(token: &str) -> anyhow::Result<isize> {
+    cell(
+        token
+            .strip_suffix(':')
+            .with_context(|| format!("expected `:` after `{token}`"))?,
+    )
+}
+
+/// undo `escape_ascii` on a quoted string
+fn unescape(quoted: &str) -> anyhow::Result<Vec<u8>> {
+    let inner = quoted
+        .trim()
+        .strip_prefix('"')
+        .and_then(|quoted| quoted.strip_suffix('"'))
+        .context("expected a quoted string")?;
+
+    let mut bytes = Vec::with_capacity(inner.len());
+    let mut chars = inner.bytes();
+    while let Some(byte) = chars.next() {
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+
+        bytes.push(match chars.next() {
+            Some(b'n') => b'\n',
+            Some(b'r') => b'\r',
+            Some(b't') => b'\t',
+            Some(b'0') => b'\0',
+            Some(b'x') => {
+                let hex = [
+                    chars.next().context("unfinished `\\x` escape")?,
+                    chars.next().context("unfinished `\\x` escape")?,
+                ];
+                u8::from_str_radix(std::str::from_utf8(&hex)?, 16).context("bad `\\x` escape")?
+            }
+            Some(escaped @ (b'\\' | b'"' | b'\'')) => escaped,
+            _ => bail!("unknown escape in string"),
+        });
+    }
+
+    Ok(bytes)
+}
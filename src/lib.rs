@@ -0,0 +1,167 @@
+//! boyfriend as a library -- parse a brainf*ck program, optimize it and run it with whatever
+//! input and output you like:
+//!
+//! ```no_run
+//! use boyfriend::{Backend, Program};
+//!
+//! let program = Program::builder().opt_level(3).parse("++++++++[>++++++++<-]>+.")?;
+//! let mut output = Vec::new();
+//! program.run(Backend::Bytecode, &mut std::io::empty(), &mut output)?;
+//! assert_eq!(output, b"A");
+//! # Ok::<(), boyfriend::Error>(())
+//! ```
+
+use std::io::{Read, Write};
+
+pub mod asm;
+pub mod bytecode;
+pub mod config;
+pub mod error;
+pub mod fuel;
+pub mod interpret;
+pub mod ir;
+pub mod ir_text;
+pub mod profile;
+
+#[cfg(feature = "cranelift")]
+pub mod cranelift;
+
+pub use config::{Bounds, CellWidth, Config, Eof, Overflow};
+pub use error::{Error, Fault};
+pub use ir::{Node, PassManager};
+
+use interpret::Reader;
+
+/// what runs a `Program`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// the bytecode interpreter -- programs with a non-default `Bounds` or `Overflow` run in the
+    /// ir interpreter instead
+    #[default]
+    Bytecode,
+    /// the ir interpreter, which handles everything `Config` can do
+    Ir,
+    /// the cranelift jit
+    #[cfg(feature = "cranelift")]
+    Jit,
+}
+
+/// sets up how a `Program` is read, optimized and run -- see `Program::builder`
+pub struct Builder {
+    config: Config,
+    passes: PassManager,
+    extensions: bool,
+}
+
+impl Builder {
+    /// run the passes for an `-O` level (3 by default)
+    pub fn opt_level(mut self, level: u8) -> Self {
+        self.passes = PassManager::new(level);
+        self
+    }
+
+    /// run exactly the passes `passes` has enabled
+    pub fn passes(mut self, passes: PassManager) -> Self {
+        self.passes = passes;
+        self
+    }
+
+    /// how the program behaves when it runs (`Config::default` by default)
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// treat `#` as "print the tape to stderr" and everything after the first `!` as the
+    /// program's input (off by default)
+    pub fn extensions(mut self, extensions: bool) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    /// read and optimize brainf*ck source
+    pub fn parse(self, source: &str) -> Result<Program, Error> {
+        let (code, input) = match self.extensions {
+            true => ir::split_input(source),
+            false => (source, None),
+        };
+        ir::verify(code)?;
+        let ir = ir::compile(code.to_string(), self.extensions);
+
+        Ok(self.optimize(ir, input.map(|input| input.as_bytes().to_vec())))
+    }
+
+    /// read and optimize textual ir, as written by `ir_text::write`
+    pub fn parse_ir(self, text: &str) -> Result<Program, Error> {
+        let ir = ir_text::parse(text, self.config.tape_size, self.config.cell_width)?;
+
+        Ok(self.optimize(ir, None))
+    }
+
+    fn optimize(self, mut ir: Vec<Node>, input: Option<Vec<u8>>) -> Program {
+        self.passes.run(&mut ir, &self.config);
+
+        Program {
+            ir,
+            config: self.config,
+            input,
+        }
+    }
+}
+
+/// an optimized program, ready to run as many times as you like
+pub struct Program {
+    ir: Vec<Node>,
+    config: Config,
+    /// the input after the `!`, with extensions
+    input: Option<Vec<u8>>,
+}
+
+impl Program {
+    /// start setting up a program, optimized at `-O3` with the default `Config`
+    pub fn builder() -> Builder {
+        Builder {
+            config: Config::default(),
+            passes: PassManager::new(3),
+            extensions: false,
+        }
+    }
+
+    /// the optimized ir
+    pub fn ir(&self) -> &[Node] {
+        &self.ir
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// the input written into the program after a `!`, which is read instead of `input` in `run`
+    pub fn input(&self) -> Option<&[u8]> {
+        self.input.as_deref()
+    }
+
+    /// run the program with `backend`, reading `input` for `,` and writing to `output`. the
+    /// output is flushed before reading input and once the program stops, error or not.
+    pub fn run(
+        &self,
+        backend: Backend,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> Result<(), Error> {
+        let reader = Reader::new(input, self.input.clone());
+
+        match backend {
+            Backend::Bytecode if bytecode::supports(&self.config) => {
+                bytecode::interpret(&self.ir, self.config, reader, output)
+            }
+            Backend::Bytecode | Backend::Ir => {
+                let mut ir = self.ir.clone();
+                ir::match_brackets(&mut ir);
+                interpret::interpret(ir, self.config, reader, output, false)
+            }
+            #[cfg(feature = "cranelift")]
+            Backend::Jit => cranelift::jit(self.ir.clone(), self.config, false, reader, output),
+        }
+    }
+}
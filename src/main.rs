@@ -1,18 +1,19 @@
 use std::fs::{File, read_to_string};
 use std::io::{BufWriter, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::{Command, exit};
+use std::time::Duration;
 
 use anyhow::bail;
-use clap::{Parser, Subcommand};
-
-mod asm;
-mod chunk_list;
-mod interpret;
-mod ir;
+use boyfriend::interpret::Reader;
+use boyfriend::{asm, bytecode, config, fuel, interpret, ir, ir_text};
+use clap::builder::PossibleValuesParser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 #[cfg(feature = "cranelift")]
-mod cranelift;
+use boyfriend::cranelift;
+
+mod debug;
 
 #[derive(Parser)]
 #[clap(version, about, subcommand_required = true, long_about = None)]
@@ -21,6 +22,181 @@ struct Cli {
     command: Commands,
 }
 
+#[derive(Args)]
+struct Optimizations {
+    /// Optimization level -- each level enables more passes
+    #[arg(short = 'O', value_name = "LEVEL", default_value_t = 3, value_parser = clap::value_parser!(u8).range(0..=3))]
+    opt_level: u8,
+
+    /// Enable an optimization pass regardless of the optimization level
+    #[arg(long = "pass", value_name = "NAME", value_parser = pass_names())]
+    passes: Vec<String>,
+
+    /// Disable an optimization pass regardless of the optimization level
+    #[arg(long = "no-pass", value_name = "NAME", value_parser = pass_names())]
+    no_passes: Vec<String>,
+
+    /// Repeat the optimization passes until they stop pruning instructions
+    #[arg(long)]
+    fixed_point: bool,
+
+    /// Print timing and pruning statistics for each optimization pass
+    #[arg(long)]
+    stats: bool,
+
+    /// Run the program at compile time until its first input (or for at most STEPS steps),
+    /// baking the output and tape state into the result (the `partial-eval` pass)
+    #[arg(
+        long,
+        verbatim_doc_comment,
+        value_name = "STEPS",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "10000000"
+    )]
+    partial_eval: Option<u64>,
+}
+
+fn pass_names() -> PossibleValuesParser {
+    PossibleValuesParser::new(ir::PASSES.map(|pass| pass.name))
+}
+
+impl Optimizations {
+    fn pass_manager(&self) -> anyhow::Result<ir::PassManager> {
+        let mut manager = ir::PassManager::new(self.opt_level);
+        manager.fixed_point = self.fixed_point;
+
+        if let Some(budget) = self.partial_eval {
+            manager.enable("partial-eval")?;
+            manager.partial_eval_budget = budget;
+        }
+        for pass in self.passes.iter() {
+            manager.enable(pass)?;
+        }
+        for pass in self.no_passes.iter() {
+            manager.disable(pass)?;
+        }
+
+        Ok(manager)
+    }
+
+    /// run the passes over `ir`, printing what each one did (and with `--stats`, how long the
+    /// passes took altogether)
+    fn optimize(&self, ir: &mut Vec<ir::Node>, config: &config::Config) -> anyhow::Result<()> {
+        let runs = self.pass_manager()?.run(ir, config);
+        for run in runs.iter() {
+            eprintln!("* {}", run.description);
+            eprintln!("* success, pruned {} instructions", run.pruned);
+        }
+
+        if self.stats {
+            eprintln!("* pass statistics:");
+            for pass in ir::PASSES.iter() {
+                let pass_runs = runs.iter().filter(|run| run.name == pass.name);
+                let (count, elapsed, pruned) =
+                    pass_runs.fold((0, Duration::ZERO, 0), |(count, elapsed, pruned), run| {
+                        (count + 1, elapsed + run.elapsed, pruned + run.pruned)
+                    });
+                if count > 0 {
+                    eprintln!(
+                        "*   {:<18} {count:>3} run(s) {:>10.3}ms  pruned {pruned}",
+                        pass.name,
+                        elapsed.as_secs_f64() * 1000.0
+                    );
+                }
+            }
+            eprintln!("*   {} instructions remaining", ir.len());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Args)]
+struct Runtime {
+    /// Width of each cell on the tape, in bits
+    #[arg(long, value_name = "BITS", value_enum, default_value = "8")]
+    cell_width: config::CellWidth,
+
+    /// Number of cells on the tape
+    #[arg(long, value_name = "N", default_value_t = 65536, value_parser = clap::value_parser!(u64).range(1..=1 << 31))]
+    tape_size: u64,
+
+    /// What happens when the pointer leaves the tape
+    #[arg(long, value_name = "POLICY", value_enum, default_value = "wrap")]
+    bounds: config::Bounds,
+
+    /// What `,` does when there's no input left
+    #[arg(long, value_name = "MODE", value_enum, default_value = "unchanged")]
+    eof: config::Eof,
+
+    /// What happens when a cell goes past the largest or smallest number it can hold (anything
+    /// but `wrap` needs the interpreter or cranelift)
+    #[arg(long, value_name = "MODE", value_enum, default_value = "wrap")]
+    overflow: config::Overflow,
+
+    /// Treat `#` as "print the tape to stderr" and everything after the first `!` as the
+    /// program's input
+    #[arg(long)]
+    extensions: bool,
+
+    /// Stop the program after this many steps -- instructions in the interpreter, trips around a
+    /// loop in the cranelift jit
+    #[arg(long, value_name = "N")]
+    max_steps: Option<u64>,
+
+    /// Stop the program once it's run for this long (eg. `500ms`, `10s` or `2m`, seconds by
+    /// default)
+    #[arg(long, value_name = "DURATION", value_parser = parse_timeout)]
+    timeout: Option<Duration>,
+}
+
+fn parse_timeout(text: &str) -> Result<Duration, String> {
+    let (number, unit) = match text.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(idx) => text.split_at(idx),
+        None => (text, "s"),
+    };
+    let number = number
+        .parse::<f64>()
+        .map_err(|_| format!("`{number}` isn't a number"))?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "s" => number,
+        "m" => number * 60.0,
+        _ => return Err(format!("`{unit}` isn't a unit -- use `ms`, `s` or `m`")),
+    };
+
+    Duration::try_from_secs_f64(seconds).map_err(|err| err.to_string())
+}
+
+impl Runtime {
+    fn config(&self) -> config::Config {
+        config::Config {
+            cell_width: self.cell_width,
+            tape_size: self.tape_size as usize,
+            bounds: self.bounds,
+            eof: self.eof,
+            overflow: self.overflow,
+            max_steps: self.max_steps,
+            timeout: self.timeout,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Interpreter {
+    /// Compile to bytecode first (only for the default `--bounds` and `--overflow`)
+    Bytecode,
+    /// Step through the intermediate representation directly
+    Ir,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Emit {
+    /// The optimized intermediate representation, as text (can be passed back in as a `.bfir` file)
+    Ir,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Homebrew implementation of the compiler -- interpreter and assembly generator
@@ -37,19 +213,77 @@ enum Commands {
         #[arg(long, verbatim_doc_comment, conflicts_with = "interpret")]
         link_libc: bool,
 
+        /// Which interpreter runs the program
+        #[arg(long, value_enum, default_value = "bytecode", requires = "interpret")]
+        interpreter: Interpreter,
+
+        /// Count how many times each instruction runs, then report the hottest loops and
+        /// instructions (and why those loops weren't collapsed into idioms)
+        #[arg(long, requires = "interpret")]
+        profile: bool,
+
+        #[command(flatten)]
+        optimizations: Optimizations,
+
+        #[command(flatten)]
+        runtime: Runtime,
+
+        /// Print an intermediate form of the program instead of compiling/running it
+        #[arg(long, value_enum)]
+        emit: Option<Emit>,
+
         /// Path to the brainf*ck file to compile/interpret (compile by default)
+        /// Files ending in `.bfir` are read as textual intermediate representation
+        #[arg(verbatim_doc_comment)]
         path: PathBuf,
     },
 
     #[cfg(feature = "cranelift")]
     /// Cranelift backend for `boyfriend` -- includes a JIT and AOT compiler
-    #[command(after_long_help = "AOT compilation requires `ld` to be installed")]
+    #[command(after_long_help = "AOT compilation requires a C compiler (`cc`) for linking")]
     Cranelift {
         #[arg(long)]
         /// Enable JIT compilation
         jit: bool,
 
+        /// Compile every top-level loop, and every loop with at least 256 instructions, into a
+        /// function of its own
+        #[arg(long)]
+        split_loops: bool,
+
+        #[command(flatten)]
+        optimizations: Optimizations,
+
+        #[command(flatten)]
+        runtime: Runtime,
+
+        /// Print an intermediate form of the program instead of compiling/running it
+        #[arg(long, value_enum)]
+        emit: Option<Emit>,
+
         /// Path to the brainf*ck file to AOT/JIT (AOT by default)
+        /// Files ending in `.bfir` are read as textual intermediate representation
+        #[arg(verbatim_doc_comment)]
+        path: PathBuf,
+    },
+
+    /// Step through a program in the interpreter, with breakpoints on source positions and cells
+    #[command(after_long_help = "Type `help` at the prompt for a list of commands")]
+    Debug {
+        #[command(flatten)]
+        optimizations: Optimizations,
+
+        #[command(flatten)]
+        runtime: Runtime,
+
+        /// Feed this file to `,` instead of reading stdin (which the debugger reads commands from)
+        /// or the input after a `!` with `--extensions`
+        #[arg(long, value_name = "PATH")]
+        input: Option<PathBuf>,
+
+        /// Path to the brainf*ck file to debug
+        /// Files ending in `.bfir` are read as textual intermediate representation
+        #[arg(verbatim_doc_comment)]
         path: PathBuf,
     },
 
@@ -88,27 +322,121 @@ fn run_command(cmd: &mut std::process::Command) -> std::io::Result<()> {
     Ok(())
 }
 
-fn moonshine_impl(interpret: bool, link_libc: bool, path: PathBuf) -> anyhow::Result<()> {
-    let code = read_to_string(&path)?;
+/// read a brainf*ck file (or IR text from a `.bfir` file) and optimize it. with `extensions`, the
+/// text after a `!` in a brainf*ck file is returned too, as the program's input.
+fn load(
+    path: &Path,
+    optimizations: &Optimizations,
+    config: &config::Config,
+    extensions: bool,
+) -> anyhow::Result<(Vec<ir::Node>, Option<Vec<u8>>)> {
+    let source = read_to_string(path)?;
+
+    let (mut ir, input) = if path
+        .extension()
+        .is_some_and(|extension| extension == "bfir")
+    {
+        (
+            ir_text::parse(&source, config.tape_size, config.cell_width)?,
+            None,
+        )
+    } else {
+        let (code, input) = match extensions {
+            true => ir::split_input(&source),
+            false => (source.as_str(), None),
+        };
+        if let Err(err @ boyfriend::Error::Brackets { .. }) = ir::verify(code) {
+            show_brackets(path, code, &err);
+            return Err(err.into());
+        }
+        (ir::compile(code.to_string(), extensions), input)
+    };
+    optimizations.optimize(&mut ir, config)?;
+
+    Ok((ir, input.map(|input| input.as_bytes().to_vec())))
+}
+
+/// print where every unmatched bracket in `err` is
+fn show_brackets(path: &Path, source: &str, err: &boyfriend::Error) {
+    let boyfriend::Error::Brackets { opening, closing } = err else {
+        return;
+    };
+
+    for &span in closing {
+        eprintln!("! unmatched closing bracket (`]`)");
+        eprint!("{}", ir::excerpt(path, source, span, 0));
+    }
+    for &span in opening {
+        eprintln!("! unmatched opening bracket (`[`)");
+        eprint!("{}", ir::excerpt(path, source, span, 0));
+    }
+}
 
-    ir::verify(&code)?;
-    let mut ir = ir::compile(code);
-    ir::collapse_repeated(&mut ir);
-    ir::collapse_idioms(&mut ir);
+fn emit_ir(ir: &[ir::Node]) -> anyhow::Result<()> {
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    ir_text::write(ir, &mut out)?;
+    out.flush()?;
+
+    Ok(())
+}
 
-    if interpret {
-        ir::match_brackets(&mut ir)?;
+/// `interpreter` is `None` when compiling to an executable
+fn moonshine_impl(
+    interpreter: Option<Interpreter>,
+    link_libc: bool,
+    profile: bool,
+    optimizations: Optimizations,
+    runtime: Runtime,
+    emit: Option<Emit>,
+    path: PathBuf,
+) -> anyhow::Result<()> {
+    let config = runtime.config();
+    let (mut ir, input) = load(&path, &optimizations, &config, runtime.extensions)?;
+
+    if emit == Some(Emit::Ir) {
+        return emit_ir(&ir);
     }
 
-    if interpret {
-        interpret::interpret(ir)?;
+    if let Some(interpreter) = interpreter {
+        let interpreter = match interpreter {
+            Interpreter::Bytecode if profile => {
+                eprintln!("* `--profile` needs the ir interpreter, switching to it");
+                Interpreter::Ir
+            }
+            Interpreter::Bytecode if !bytecode::supports(&config) => {
+                eprintln!("* `--bounds` and `--overflow` need the ir interpreter, switching to it");
+                Interpreter::Ir
+            }
+            interpreter => interpreter,
+        };
+
+        let mut stdin = std::io::stdin();
+        let input = Reader::new(&mut stdin, input);
+        let mut stdout = std::io::stdout();
+        match interpreter {
+            Interpreter::Bytecode => bytecode::interpret(&ir, config, input, &mut stdout)?,
+            Interpreter::Ir => {
+                eprintln!("* matching brackets");
+                ir::match_brackets(&mut ir);
+                interpret::interpret(ir, config, input, &mut stdout, profile)?;
+            }
+        }
     } else {
+        if config.overflow != config::Overflow::Wrap {
+            bail!("only wrapping cells can be compiled to assembly -- interpret with `-i` instead");
+        }
+        if config.max_steps.is_some() || config.timeout.is_some() {
+            bail!(
+                "`--max-steps` and `--timeout` can't be compiled to assembly -- interpret with `-i` instead"
+            );
+        }
+
         let mut asm_path = path.clone();
         asm_path.set_extension("asm");
 
         let mut out = BufWriter::new(File::create(&asm_path)?);
 
-        asm::to_asm(link_libc, ir, &mut out)?;
+        asm::to_asm(link_libc, ir, config, input, &mut out)?;
 
         eprintln!(
             "* compilation success, writing assembly to {}",
@@ -147,20 +475,27 @@ fn moonshine_impl(interpret: bool, link_libc: bool, path: PathBuf) -> anyhow::Re
 }
 
 #[cfg(feature = "cranelift")]
-fn cranelift_impl(jit: bool, path: PathBuf) -> anyhow::Result<()> {
-    let code = read_to_string(&path)?;
-
-    ir::verify(&code)?;
-    let mut ir = ir::compile(code);
-    ir::collapse_repeated(&mut ir);
-    if !jit {
-        ir::collapse_idioms(&mut ir);
+fn cranelift_impl(
+    jit: bool,
+    split_loops: bool,
+    optimizations: Optimizations,
+    runtime: Runtime,
+    emit: Option<Emit>,
+    path: PathBuf,
+) -> anyhow::Result<()> {
+    let config = runtime.config();
+    let (ir, input) = load(&path, &optimizations, &config, runtime.extensions)?;
+
+    if emit == Some(Emit::Ir) {
+        return emit_ir(&ir);
     }
 
     if jit {
-        cranelift::jit(ir)?;
+        let mut stdin = std::io::stdin();
+        let input = Reader::new(&mut stdin, input);
+        cranelift::jit(ir, config, split_loops, input, &mut std::io::stdout())?;
     } else {
-        let object = cranelift::aot(ir)?;
+        let object = cranelift::aot(ir, config, split_loops, input)?;
 
         let mut object_path = path.clone();
         object_path.set_extension("o");
@@ -173,9 +508,12 @@ fn cranelift_impl(jit: bool, path: PathBuf) -> anyhow::Result<()> {
         let mut binary_path = path.clone();
         binary_path.set_extension("");
 
+        eprintln!("* linking binary with cc");
+
+        // `cc` brings in the C runtime, which sets up libc, calls `main` and exits with what it
+        // returns
         run_command(
-            Command::new("ld")
-                .args(["-dynamic-linker", "/lib64/ld-linux-x86-64.so.2", "-lc"])
+            Command::new("cc")
                 .arg("-o")
                 .arg(&binary_path)
                 .arg(&object_path),
@@ -191,10 +529,45 @@ fn entry() -> anyhow::Result<()> {
         Commands::Moonshine {
             interpret,
             link_libc,
+            interpreter,
+            profile,
+            optimizations,
+            runtime,
+            emit,
             path,
-        } => moonshine_impl(interpret, link_libc, path)?,
+        } => moonshine_impl(
+            interpret.then_some(interpreter),
+            link_libc,
+            profile,
+            optimizations,
+            runtime,
+            emit,
+            path,
+        )?,
         #[cfg(feature = "cranelift")]
-        Commands::Cranelift { jit, path } => cranelift_impl(jit, path)?,
+        Commands::Cranelift {
+            jit,
+            split_loops,
+            optimizations,
+            runtime,
+            emit,
+            path,
+        } => cranelift_impl(jit, split_loops, optimizations, runtime, emit, path)?,
+        Commands::Debug {
+            optimizations,
+            runtime,
+            input,
+            path,
+        } => {
+            let config = runtime.config();
+            if config.max_steps.is_some() || config.timeout.is_some() {
+                eprintln!("* the debugger ignores `--max-steps` and `--timeout`");
+            }
+            let source = read_to_string(&path)?;
+            let (ir, inline) = load(&path, &optimizations, &config, runtime.extensions)?;
+            let input = input.map(std::fs::read).transpose()?.or(inline);
+            debug::debug(&path, &source, ir, config, input)?;
+        }
         Commands::Clean { path } => {
             let mut asm_path = path.clone();
             asm_path.set_extension("asm");
@@ -232,10 +605,19 @@ fn entry() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// report `err` and exit -- with `fuel::EXIT_CODE` if the program ran out of steps or time
+fn fatal(err: anyhow::Error) -> ! {
+    let msg = format!("! fatal error: {err:?}").to_lowercase();
+    println!("{msg}");
+
+    match err.downcast_ref::<boyfriend::Error>() {
+        Some(boyfriend::Error::Exhausted(_)) => exit(fuel::EXIT_CODE),
+        _ => exit(1),
+    }
+}
+
 fn main() {
     if let Err(err) = entry() {
-        let msg = format!("! fatal error: {err:?}").to_lowercase();
-        println!("{msg}");
-        exit(1);
+        fatal(err);
     }
 }
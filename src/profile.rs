@@ -0,0 +1,107 @@
+use std::cmp::Reverse;
+
+use crate::config::Config;
+use crate::ir::IR::{self, *};
+use crate::ir::Span;
+
+/// the number of loops and instructions shown in the report
+const TOP: usize = 20;
+
+/// why the loop from `start` to `end` (the indices of its brackets) is still a loop and not an
+/// idiom -- the things `collapse_idioms` would need to learn about
+fn blocker(insts: &[IR], start: usize, end: usize, config: &Config) -> String {
+    let mut position = 0;
+    // what the body adds to the cell the loop checks
+    let mut step = 0i64;
+
+    for inst in &insts[start + 1..end] {
+        match *inst {
+            LoopStart { .. } => return "has a loop inside".to_string(),
+            Input { .. } | Output { .. } | Print { .. } | DebugDump => {
+                return "does i/o".to_string();
+            }
+            Scan { .. } => return "scans".to_string(),
+            Shift { amount } => position += amount,
+            Arithmetic { amount, offset } => {
+                if position + offset == 0 {
+                    step = step.wrapping_add(amount);
+                }
+            }
+            _ => return format!("has `{inst}` inside"),
+        }
+    }
+
+    if position != 0 {
+        return format!("moves the pointer by {position:+}");
+    }
+    match config.cell_width.wrap_signed(step) {
+        -1 => "looks collapsible -- is collapse-idioms on?".to_string(),
+        0 => "doesn't change the cell it checks".to_string(),
+        step => format!("steps the cell it checks by {step:+}, not -1"),
+    }
+}
+
+/// print where the interpreter spent its time, given how many times each instruction ran.
+/// `insts` must have its brackets matched.
+pub fn report(insts: &[IR], spans: &[Span], counts: &[u64], config: &Config) {
+    let total = counts.iter().sum::<u64>();
+    let share = |count: u64| count as f64 * 100.0 / total.max(1) as f64;
+    eprintln!("* profile: {total} instructions run");
+
+    // (steps inside the loop, the index of its `[`, the index of its `]`)
+    let mut loops = insts
+        .iter()
+        .enumerate()
+        .filter_map(|(start, inst)| match *inst {
+            LoopStart { end_index } => {
+                let steps = counts[start..=end_index].iter().sum::<u64>();
+                Some((steps, start, end_index))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    loops.sort_by_key(|&(steps, ..)| Reverse(steps));
+
+    eprintln!(
+        "* hottest loops ({} in total, steps include the loops inside them):",
+        loops.len()
+    );
+    eprintln!(
+        "*   {:>12} {:>7}  {:<10} {:>10} {:>12}  why it's still a loop",
+        "steps", "share", "where", "runs", "iterations"
+    );
+    for &(steps, start, end) in loops.iter().take(TOP) {
+        if steps == 0 {
+            break;
+        }
+        eprintln!(
+            "*   {steps:>12} {:>6.2}%  {:<10} {:>10} {:>12}  {}",
+            share(steps),
+            spans[start].to_string(),
+            counts[start],
+            counts[end],
+            blocker(insts, start, end, config)
+        );
+    }
+
+    let mut hottest = (0..insts.len()).collect::<Vec<_>>();
+    hottest.sort_by_key(|&idx| Reverse(counts[idx]));
+
+    eprintln!("* hottest instructions:");
+    eprintln!(
+        "*   {:>12} {:>7}  {:<10} instruction",
+        "runs", "share", "where"
+    );
+    for &idx in hottest.iter().take(TOP) {
+        if counts[idx] == 0 {
+            break;
+        }
+        eprintln!(
+            "*   {:>12} {:>6.2}%  {:<10} {}",
+            counts[idx],
+            share(counts[idx]),
+            spans[idx].to_string(),
+            insts[idx]
+        );
+    }
+}
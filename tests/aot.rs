@@ -0,0 +1,86 @@
+//! builds executables with `boyfriend cranelift` and runs them
+
+#![cfg(feature = "cranelift")]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// a scratch directory for the build artifacts, so the bundled tests stay clean
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("boyfriend-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn boyfriend(args: &[&str], path: &Path) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_boyfriend"))
+        .args(args)
+        .arg(path)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "boyfriend {args:?} failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+/// build `program` with `boyfriend cranelift` and `flags`, and check that the executable prints
+/// `expected`
+fn compile_and_run(flags: &[&str], program: &Path, expected: &[u8]) {
+    let name = program.file_stem().unwrap().to_str().unwrap();
+    let dir = scratch(&format!("{name}-{}", flags.join("")));
+    let source = dir.join(program.file_name().unwrap());
+    std::fs::copy(program, &source).unwrap();
+
+    boyfriend(&[&["cranelift"], flags].concat(), &source);
+    let aot = Command::new(dir.join(name)).output().unwrap();
+
+    assert!(aot.status.success());
+    assert_eq!(aot.stdout, expected, "with {flags:?}");
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+fn bundled_mandelbrot() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/mandelbrot/mandelbrot.bf")
+}
+
+#[test]
+fn mandelbrot() {
+    let program = bundled_mandelbrot();
+    let jit = boyfriend(&["cranelift", "--jit"], &program);
+
+    assert!(!jit.stdout.is_empty());
+    compile_and_run(&[], &program, &jit.stdout);
+}
+
+#[test]
+fn split_loops() {
+    let program = bundled_mandelbrot();
+    let jit = boyfriend(&["cranelift", "--jit"], &program);
+
+    compile_and_run(&["--split-loops"], &program, &jit.stdout);
+}
+
+#[test]
+fn exit_status() {
+    let dir = scratch("exit-status");
+    let source = dir.join("left.bf");
+    std::fs::write(&source, "+++.[<+]").unwrap();
+
+    boyfriend(&["cranelift", "--bounds", "abort"], &source);
+    let aot = Command::new(dir.join("left")).output().unwrap();
+
+    assert_eq!(aot.status.code(), Some(1));
+    // what was printed before the program stopped still comes out
+    assert!(aot.stdout.starts_with(b"\x03"));
+    // and the fault reads the same as it does from the jit
+    assert!(String::from_utf8_lossy(&aot.stdout).ends_with(
+        "! fatal error: in the instruction at 1:7: the pointer left the tape -- cell -1 is out of \
+         bounds (the tape has 65536 cells)\n"
+    ));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
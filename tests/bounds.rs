@@ -0,0 +1,44 @@
+//! `--bounds abort` stops a program at the same instruction at every optimization level
+
+use boyfriend::{Backend, Bounds, Config, Overflow, Program};
+
+/// the output of running `source` with `backend` at `opt_level`, and what stopped it early
+fn run(source: &str, backend: Backend, opt_level: u8) -> (Vec<u8>, Option<String>) {
+    let config = Config {
+        tape_size: 9,
+        bounds: Bounds::Abort,
+        overflow: Overflow::Unsigned,
+        ..Config::default()
+    };
+    let program = Program::builder()
+        .opt_level(opt_level)
+        .config(config)
+        .parse(source)
+        .unwrap();
+
+    let mut output = Vec::new();
+    let result = program.run(backend, &mut &b"\x05"[..], &mut output);
+    (output, result.err().map(|err| err.to_string()))
+}
+
+#[test]
+fn optimizations_agree() {
+    let backends = [
+        Backend::Ir,
+        #[cfg(feature = "cranelift")]
+        Backend::Jit,
+    ];
+
+    // leaving the tape and coming back is fine, only touching a cell off it isn't
+    for source in ["<<>>-", "<>>>>-.,[>>]", "+.<<+>>", "+[>+++.<<<<-]"] {
+        let unoptimized = run(source, Backend::Ir, 0);
+        assert!(unoptimized.1.is_some(), "{source}");
+
+        for backend in backends {
+            for opt_level in [0, 2] {
+                let optimized = run(source, backend, opt_level);
+                assert_eq!(optimized, unoptimized, "{source} at -O{opt_level}");
+            }
+        }
+    }
+}
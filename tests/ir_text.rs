@@ -0,0 +1,93 @@
+//! reads textual ir back in with `ir_text::parse` and `Program::parse_ir`
+
+use boyfriend::ir::IR::*;
+use boyfriend::ir::Span;
+use boyfriend::{Backend, CellWidth, Config, Error, Node, Program, ir_text};
+
+#[test]
+fn preload_longer_than_the_tape() {
+    let cells = vec!["01"; 32].join(" ");
+    let config = Config {
+        tape_size: 16,
+        ..Config::default()
+    };
+    let result = Program::builder()
+        .config(config)
+        .parse_ir(&format!("preload {cells}\nout @+0\n"));
+
+    assert!(matches!(result, Err(Error::Parse { line: 1, .. })));
+}
+
+#[test]
+fn values_wider_than_a_cell() {
+    for (text, width, line) in [
+        ("set 300 @+0\nout @+0\n", CellWidth::W8, 1),
+        ("add +1 @+0\nscan +1 until 65536\n", CellWidth::W16, 2),
+    ] {
+        let config = Config {
+            cell_width: width,
+            ..Config::default()
+        };
+        let result = Program::builder().config(config).parse_ir(text);
+
+        assert!(
+            matches!(result, Err(Error::Parse { line: at, .. }) if at == line),
+            "{text:?}"
+        );
+    }
+
+    let config = Config {
+        cell_width: CellWidth::W16,
+        ..Config::default()
+    };
+    let mut output = Vec::new();
+    Program::builder()
+        .opt_level(0)
+        .config(config)
+        .parse_ir("set 300 @+0\nout @+0\n")
+        .unwrap()
+        .run(Backend::Bytecode, &mut std::io::empty(), &mut output)
+        .unwrap();
+    assert_eq!(output, b",");
+}
+
+#[test]
+fn comments_after_print() {
+    let insts = vec![
+        Print {
+            bytes: b"# not a \"comment\" \\#".to_vec(),
+        },
+        Arithmetic {
+            amount: 35,
+            offset: 0,
+        },
+        Output { offset: 0 },
+    ];
+    let ir = insts
+        .iter()
+        .map(|inst| Node {
+            inst: inst.clone(),
+            span: Span::at(1, 1),
+        })
+        .collect::<Vec<_>>();
+
+    let mut text = Vec::new();
+    ir_text::write(&ir, &mut text).unwrap();
+    let commented = String::from_utf8(text)
+        .unwrap()
+        .lines()
+        .map(|line| format!("{line} # \"#\" comment\n"))
+        .collect::<String>();
+
+    let parsed = ir_text::parse(&commented, 16, CellWidth::W8).unwrap();
+    assert!(parsed.into_iter().map(|node| node.inst).eq(insts));
+
+    let mut output = Vec::new();
+    Program::builder()
+        .opt_level(0)
+        .parse_ir(&commented)
+        .unwrap()
+        .run(Backend::Bytecode, &mut std::io::empty(), &mut output)
+        .unwrap();
+    assert_eq!(output, b"# not a \"comment\" \\##");
+}
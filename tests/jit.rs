@@ -0,0 +1,50 @@
+//! runs programs in the cranelift jit with and without loops split off into functions
+
+#![cfg(feature = "cranelift")]
+
+use boyfriend::interpret::Reader;
+use boyfriend::{Bounds, Config, Overflow, Program, cranelift};
+
+/// the output of running `source` in the jit, and what stopped it early
+fn run(source: &str, config: Config, split_loops: bool) -> (Vec<u8>, Option<String>) {
+    let program = Program::builder().config(config).parse(source).unwrap();
+
+    let mut output = Vec::new();
+    let result = cranelift::jit(
+        program.ir().to_vec(),
+        config,
+        split_loops,
+        Reader::new(&mut std::io::empty(), None),
+        &mut output,
+    );
+    (output, result.err().map(|err| err.to_string()))
+}
+
+#[test]
+fn split_loops_stop_the_same() {
+    // the fault comes from a loop two loops down, after some output from its sibling -- neither
+    // is a plain multiplication, so both stay loops
+    for (source, config) in [
+        (
+            "++[>+++[>+.<-]>[<<<<<+>>>>>-<]<<-]",
+            Config {
+                bounds: Bounds::Abort,
+                ..Config::default()
+            },
+        ),
+        (
+            "++[>+++[>+.<-]>[>-<-.]<<-]",
+            Config {
+                overflow: Overflow::Unsigned,
+                ..Config::default()
+            },
+        ),
+    ] {
+        let default = run(source, config, false);
+        let split = run(source, config, true);
+
+        assert_eq!(default.0, b"\x01\x02\x03", "{source}");
+        assert!(default.1.is_some(), "{source}");
+        assert_eq!(split, default, "{source}");
+    }
+}
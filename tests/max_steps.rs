@@ -0,0 +1,34 @@
+//! `max_steps` stops both interpreters at the same instruction
+
+use boyfriend::{Backend, Config, Program};
+
+/// the output of running `source` with `backend`, and what stopped it early
+fn run(source: &str, backend: Backend, max_steps: u64) -> (Vec<u8>, Option<String>) {
+    let config = Config {
+        max_steps: Some(max_steps),
+        ..Config::default()
+    };
+    let program = Program::builder().config(config).parse(source).unwrap();
+
+    let mut output = Vec::new();
+    let result = program.run(backend, &mut &b"\x03\x02"[..], &mut output);
+    (output, result.err().map(|err| err.to_string()))
+}
+
+#[test]
+fn interpreters_agree() {
+    // multiplies with more than one target and shifts right before a `]` are more than one op in
+    // the bytecode, but one step each
+    let source = ",[>++>+++<<-]>>[<+>-]<[>+>+<<-]>>.,[->+<]>[.>]";
+
+    for max_steps in 1.. {
+        let bytecode = run(source, Backend::Bytecode, max_steps);
+        let ir = run(source, Backend::Ir, max_steps);
+        assert_eq!(bytecode, ir, "with max_steps {max_steps}");
+
+        if bytecode.1.is_none() {
+            assert!(max_steps > 10);
+            break;
+        }
+    }
+}